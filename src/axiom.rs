@@ -1,12 +1,13 @@
-use bevy::ecs::system::ResMut;
+use crate::{map::{Species, Map, Neighbourhood}, simulation::get_adjacent_coords};
 
-use crate::{map::{Species, Map}, simulation::get_adjacent_coords};
-
-#[derive(Clone, PartialEq, Debug, Copy)]
+#[derive(Clone, PartialEq, Debug, Copy, Default)]
 pub enum Axiom{
     Move{dx: i32, dy: i32},
     PaintAdjacent{ color: Species },
     SpeciesTransform{new_species: Species},
+    Tag, // Catches the Psychics standing next to it. Only Hunters get it.
+    Teleport{x: u32, y: u32}, // Straight to that tile, if it is free. Only scripted Hylics use it.
+    #[default]
     Void
 }
 
@@ -26,20 +27,28 @@ impl Axiom{
     pub fn act_axioms(
         self,
         pos: (u32, u32),
-        map: &ResMut<Map>, // Don't place stuff that's not on top of an entity, it will stay there.
+        map: &Map, // Don't place stuff that's not on top of an entity, it will stay there.
     ) -> Vec<(Axiom, (u32, u32))>{
         let mut output = Vec::new();
         match self{
             Axiom::PaintAdjacent {color} => {
-                for i in get_adjacent_coords(pos, 1){
-                    if map.tiles[map.xy_idx(i.0, i.1)] == Species::Wall{
+                for i in get_adjacent_coords(pos, Neighbourhood::moore(1).with_out_of_bounds(map.out_of_bounds), map){
+                    let tile = map.tiles[map.xy_idx(i.0, i.1)];
+                    if tile == Species::Wall || (tile.is_paint() && tile != color){ // The other faction's paint gets painted over.
                         output.push((Axiom::SpeciesTransform { new_species: color }, i));
                     }
-                    else if map.tiles[map.xy_idx(i.0, i.1)] == Species::TermiPainted{ // so we can deduct points
+                    else if tile == color{ // so we can deduct points
                         output.push((Axiom::Void, i));
                     }
                 }
             },
+            Axiom::Tag => {
+                for i in get_adjacent_coords(pos, Neighbourhood::moore(1).with_out_of_bounds(map.out_of_bounds), map){
+                    if map.tiles[map.xy_idx(i.0, i.1)] == Species::Psychic{
+                        output.push((Axiom::SpeciesTransform { new_species: Species::Caught }, i));
+                    }
+                }
+            },
             _ => ()
         };
         output
@@ -49,7 +58,9 @@ impl Axiom{
 pub enum AxiomKit{
     Motion,
     PaintKit,
-    OnlyPaint
+    OnlyPaint,
+    HuntKit,
+    TeamKit(Species), // Like the PaintKit, in the colours of a paintball faction.
 }
 
 impl AxiomKit{
@@ -57,6 +68,8 @@ impl AxiomKit{
         match self{
             AxiomKit::Motion => vec![Axiom::Move { dx: 0, dy: 1 }, Axiom::Move { dx: 0, dy: -1 }, Axiom::Move { dx: -1, dy: 0 }, Axiom::Move { dx: 1, dy: 0 }, Axiom::Move { dx: 0, dy: 0 }], // this might not be that good - hard to encourage action diversity by fitness? See Tango Problem
             AxiomKit::PaintKit => vec![Axiom::Move { dx: 0, dy: 1 }, Axiom::Move { dx: 0, dy: -1 }, Axiom::Move { dx: -1, dy: 0 }, Axiom::Move { dx: 1, dy: 0 }, Axiom::PaintAdjacent {color: Species::TermiPainted}],
+            AxiomKit::HuntKit => vec![Axiom::Move { dx: 0, dy: 1 }, Axiom::Move { dx: 0, dy: -1 }, Axiom::Move { dx: -1, dy: 0 }, Axiom::Move { dx: 1, dy: 0 }, Axiom::Tag],
+            AxiomKit::TeamKit(team) => vec![Axiom::Move { dx: 0, dy: 1 }, Axiom::Move { dx: 0, dy: -1 }, Axiom::Move { dx: -1, dy: 0 }, Axiom::Move { dx: 1, dy: 0 }, Axiom::PaintAdjacent {color: team.paint()}],
             AxiomKit::OnlyPaint => vec![Axiom::PaintAdjacent {color: Species::TermiPainted},Axiom::PaintAdjacent {color: Species::TermiPainted},Axiom::PaintAdjacent {color: Species::TermiPainted},Axiom::PaintAdjacent {color: Species::TermiPainted},Axiom::PaintAdjacent {color: Species::TermiPainted}],
         }
     }
@@ -0,0 +1,733 @@
+use std::{collections::VecDeque, fs, io::{self, BufWriter, Write}, path::{Path, PathBuf}};
+
+use bevy::{app::AppExit, ecs::system::SystemParam, prelude::*};
+use rand::SeedableRng;
+use rand_chacha::ChaCha12Rng;
+
+use crate::{axiom::Axiom, config::Config, energy::{Energy, Exhaustion}, map::{Map, NeighbourhoodShape, OutOfBounds, Species, Topology}, nn::Net, novelty::{Behaviour, NoveltyArchive}, pareto::{EliteMember, ParetoElite}, psychics::{HylicBundle, PsychicBundle, Position, Soul, Trace}, replay::{ByteReader, ReplayRecorder, tile_count, axiom_to_bytes, byte_to_species, read_axiom, invalid, species_to_byte}, simulation::{sense_count, EvolutionSettings, FitnessWeights, GenerationFinished, GenerationStats, NextEvolutionSettings, Selection, SimulationSettings, StatsHistory, TrainingRng}, speciation::{Clade, Speciation}, hall_of_fame::{Champion, HallOfFame, PendingTrials}, coevolution::Rivalry};
+
+pub struct CheckpointPlugin;
+
+impl Plugin for CheckpointPlugin {
+    fn build(&self, app: &mut App) {
+        let config = app.world.get_resource::<Config>().cloned().unwrap_or_default();
+        if let Some(path) = config.resume {
+            match Checkpoint::load(&path) {
+                Ok(checkpoint) => {
+                    app.insert_resource(Resume(checkpoint));
+                    app.add_systems(PostStartup, resume_training); // Once the creatures of distribute_psychics exist, to replace them.
+                }
+                Err(e) => panic!("Could not resume from {}: {e}", path.display()), // Better than silently starting over.
+            }
+        }
+        if let Some(interval) = config.checkpoint_interval {
+            app.insert_resource(Checkpointer{path: config.checkpoint, interval});
+            app.insert_resource(Boundary(None));
+            app.add_systems(PostStartup, remember_boundary.after(resume_training));
+            app.add_systems(Last, (remember_boundary, save_checkpoint).chain()); // After the next generation has been set up.
+        }
+    }
+}
+
+const MAGIC: &[u8; 4] = b"TGFC";
+const VERSION: u8 = 1;
+
+#[derive(Resource)]
+pub struct Checkpointer {
+    pub path: PathBuf,
+    pub interval: usize, // In generations.
+}
+
+#[derive(Resource)]
+struct Resume(Checkpoint);
+
+#[derive(Resource)]
+pub struct Boundary(pub Option<Checkpoint>); // The run at the start of its current generation, what quitting halfway through it saves.
+
+pub struct SavedPsychic {
+    pub position: (u32, u32), // Where it starts its generation.
+    pub brain: Net,
+    pub action_choices: Vec<Axiom>,
+    pub actions_chosen: Vec<(i32, i32)>, // Kept for its whole life, not just a generation. See Soul.
+    pub fitness: f32,
+    pub stand_in: bool,
+}
+
+pub struct Checkpoint { // A training run, as it was at the start of its current generation. Resuming plays that generation again from its first turn.
+    pub generation: usize,
+    pub width: u32,
+    pub height: u32,
+    pub topology: Topology,
+    pub out_of_bounds: OutOfBounds,
+    pub sense_shape: NeighbourhoodShape, // These three decide how many senses the brains were built for.
+    pub sense_centre: bool,
+    pub tiles: Vec<Species>,
+    pub psychics: Vec<SavedPsychic>,
+    pub evolution: EvolutionSettings, // Including the changes waiting for the next generation.
+    pub best_fitness: f32, // For RecordPolicy::Improving.
+    pub archive: Vec<Behaviour>,
+    pub speciation: Speciation,
+    pub champions: Vec<Champion>,
+    pub rivals: Vec<(Species, Vec<Net>)>, // See coevolution::Rivalry. The population each creature belongs to is in the tiles.
+    pub energy: Option<Energy>, // Adds a sense, so it is the checkpoint's that counts, not --energy.
+    pub elite: Vec<EliteMember>, // Empty unless selecting with Pareto.
+    pub rng: ChaCha12Rng,
+    pub history: Vec<GenerationStats>,
+    pub population: Vec<Species>, // The map recipe, in order: build_map places them one by one.
+}
+
+#[derive(SystemParam)]
+pub struct TrainingState<'w, 's> { // Everything a checkpoint is made of.
+    simulation: Res<'w, SimulationSettings>,
+    next: Res<'w, NextEvolutionSettings>,
+    map: Res<'w, Map>,
+    recorder: Res<'w, ReplayRecorder>,
+    archive: Res<'w, NoveltyArchive>,
+    speciation: Res<'w, Speciation>,
+    hall: ResMut<'w, HallOfFame>,
+    trials: ResMut<'w, PendingTrials>, // Waited for, so the champions still in their trials are not lost.
+    rivalry: Option<Res<'w, Rivalry>>, // Only with Hunters.
+    elite: Res<'w, ParetoElite>,
+    rng: Res<'w, TrainingRng>,
+    history: Res<'w, StatsHistory>,
+    psychics: Query<'w, 's, (&'static Position, &'static Soul)>,
+}
+
+impl TrainingState<'_, '_> {
+    fn snapshot(&self) -> Checkpoint {
+        Checkpoint{
+            generation: self.simulation.current_generation,
+            width: self.map.width,
+            height: self.map.height,
+            topology: self.map.topology,
+            out_of_bounds: self.map.out_of_bounds,
+            sense_shape: self.map.sense_shape,
+            sense_centre: self.map.sense_centre,
+            tiles: self.map.starting_tiles.clone(),
+            psychics: self.psychics.iter()
+                .filter(|(position, _)| !position.benched)
+                .map(|(position, soul)| SavedPsychic{position: position.starting_position, brain: soul.nn.clone(), action_choices: soul.action_choices.clone(), actions_chosen: soul.actions_chosen.clone(), fitness: soul.fitness, stand_in: soul.stand_in})
+                .collect(),
+            evolution: self.next.0.clone(),
+            best_fitness: self.recorder.best_fitness,
+            archive: self.archive.behaviours.iter().cloned().collect(),
+            speciation: self.speciation.clone(),
+            champions: self.hall.champions.clone(),
+            rivals: self.rivalry.as_ref().map(|r| r.archives.iter().map(|(s, a)| (*s, a.iter().cloned().collect())).collect()).unwrap_or_default(),
+            energy: self.map.energy,
+            elite: self.elite.members.clone(),
+            rng: self.rng.0.clone(),
+            history: self.history.0.clone(),
+            population: self.map.population.clone(),
+        }
+    }
+    fn save(&mut self, checkpoint: &mut Checkpoint, path: &Path) {
+        self.hall.settle(&mut self.trials);
+        checkpoint.champions = self.hall.champions.clone(); // Their trials started before this generation did.
+        match checkpoint.save(path) {
+            Ok(()) => info!("Saved a checkpoint to {}, it resumes at generation {}.", path.display(), checkpoint.generation),
+            Err(e) => warn!("Could not save a checkpoint to {}: {e}", path.display()),
+        }
+    }
+}
+
+fn remember_boundary( // Once per generation, before its first turn. Nothing of it has been played or rolled yet.
+    state: TrainingState,
+    mut boundary: ResMut<Boundary>,
+){
+    let remembered = boundary.0.as_ref().is_some_and(|c| c.generation == state.simulation.current_generation);
+    if state.simulation.current_turn == 0 && !remembered {
+        boundary.0 = Some(state.snapshot());
+    }
+}
+
+fn save_checkpoint( // Every few generations, and when quitting: closing the window or reaching --generations. Mid-generation, the one in progress will be played again.
+    mut finished: EventReader<GenerationFinished>,
+    mut exit: EventReader<AppExit>,
+    checkpointer: Res<Checkpointer>,
+    mut boundary: ResMut<Boundary>,
+    mut state: TrainingState,
+){
+    let due = finished.read().any(|event| event.generation > 0 && event.generation % checkpointer.interval == 0);
+    let quitting = exit.read().count() > 0;
+    if let Some(checkpoint) = boundary.0.as_mut().filter(|_| due || quitting) {
+        state.save(checkpoint, &checkpointer.path);
+    }
+}
+
+fn resume_training( // Swaps whatever distribute_psychics made for the checkpoint.
+    world: &mut World,
+){
+    let Some(Resume(checkpoint)) = world.remove_resource::<Resume>() else { return };
+    let creatures: Vec<Entity> = world.query_filtered::<Entity, With<Trace>>().iter(world).collect();
+    for entity in creatures {
+        world.despawn(entity); // The theatre forgets their actors at the next Space.
+    }
+    {
+        let mut map = world.resource_mut::<Map>();
+        (map.width, map.height) = (checkpoint.width, checkpoint.height);
+        (map.topology, map.out_of_bounds) = (checkpoint.topology, checkpoint.out_of_bounds);
+        (map.sense_shape, map.sense_centre) = (checkpoint.sense_shape, checkpoint.sense_centre);
+        map.tiles = checkpoint.tiles.clone();
+        map.starting_tiles = checkpoint.tiles.clone();
+        map.axiom_map = vec![Axiom::Void; checkpoint.tiles.len()];
+        map.population = checkpoint.population.clone();
+        map.rival_senses = map.population.contains(&Species::Hunter);
+        map.teams = map.population.contains(&Species::Red);
+        map.energy = checkpoint.energy;
+    }
+    for (index, species) in checkpoint.tiles.iter().enumerate(){
+        let (x, y) = (index as u32 % checkpoint.width, index as u32 / checkpoint.width);
+        if matches!(species, Species::Wall | Species::Beacon) {
+            let script = world.resource::<Map>().script_for(*species);
+            world.spawn(HylicBundle::new().with_position(x, y).with_species(*species).with_script(script).with_first_turn());
+        }
+    }
+    let psychics = checkpoint.psychics.len();
+    for psychic in checkpoint.psychics {
+        let (x, y) = psychic.position;
+        let species = match checkpoint.tiles[(y * checkpoint.width + x) as usize] {
+            species if species.is_psychic() => species,
+            _ => Species::Psychic,
+        };
+        let mut spawned = world.spawn(PsychicBundle::new().with_position(x, y).with_species(species).with_brain(psychic.brain, psychic.action_choices).with_first_turn());
+        let mut soul = spawned.get_mut::<Soul>().unwrap();
+        (soul.actions_chosen, soul.fitness, soul.stand_in) = (psychic.actions_chosen, psychic.fitness, psychic.stand_in);
+    }
+    {
+        let mut simulation = world.resource_mut::<SimulationSettings>();
+        simulation.current_generation = checkpoint.generation;
+        simulation.current_turn = 0; // Not the end of a generation, so nothing gets evolved before it is played.
+        simulation.max_turn_number = checkpoint.evolution.max_turn_number;
+    }
+    world.insert_resource(checkpoint.evolution.clone());
+    world.insert_resource(NextEvolutionSettings(checkpoint.evolution));
+    world.resource_mut::<ReplayRecorder>().best_fitness = checkpoint.best_fitness;
+    world.insert_resource(NoveltyArchive{behaviours: checkpoint.archive.into_iter().collect::<VecDeque<_>>()});
+    world.insert_resource(checkpoint.speciation);
+    world.insert_resource(ParetoElite{members: checkpoint.elite});
+    world.insert_resource(StatsHistory(checkpoint.history));
+    world.insert_resource(TrainingRng(checkpoint.rng)); // Last, distribute_psychics already rolled the fresh dice.
+    let mut hall = world.resource_mut::<HallOfFame>();
+    hall.champions = checkpoint.champions;
+    hall.rank(); // In case it was resumed with a smaller --hall-size.
+    if let Some(mut rivalry) = world.get_resource_mut::<Rivalry>() {
+        rivalry.archives = checkpoint.rivals.into_iter().map(|(s, a)| (s, a.into_iter().collect())).collect();
+    }
+    info!("Resumed at generation {} with {psychics} Psychics.", checkpoint.generation);
+}
+
+impl Checkpoint {
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temporary = path.with_extension("tmp"); // Written next to it then renamed, so a crash mid-save leaves the last one intact.
+        let mut out = BufWriter::new(fs::File::create(&temporary)?);
+        out.write_all(MAGIC)?;
+        out.write_all(&[VERSION])?;
+        out.write_all(&(self.generation as u32).to_le_bytes())?;
+        out.write_all(&self.width.to_le_bytes())?;
+        out.write_all(&self.height.to_le_bytes())?;
+        out.write_all(&[topology_to_byte(self.topology), out_of_bounds_to_byte(self.out_of_bounds), shape_to_byte(self.sense_shape), self.sense_centre as u8])?;
+        let tiles: Vec<u8> = self.tiles.iter().map(|s| species_to_byte(*s)).collect();
+        out.write_all(&tiles)?;
+        out.write_all(&(self.psychics.len() as u32).to_le_bytes())?;
+        for psychic in self.psychics.iter(){
+            out.write_all(&psychic.position.0.to_le_bytes())?;
+            out.write_all(&psychic.position.1.to_le_bytes())?;
+            write_net(&mut out, &psychic.brain)?;
+            out.write_all(&(psychic.action_choices.len() as u32).to_le_bytes())?;
+            for choice in psychic.action_choices.iter(){
+                out.write_all(&axiom_to_bytes(*choice))?;
+            }
+            out.write_all(&(psychic.actions_chosen.len() as u32).to_le_bytes())?;
+            for (dx, dy) in psychic.actions_chosen.iter(){
+                out.write_all(&dx.to_le_bytes())?;
+                out.write_all(&dy.to_le_bytes())?;
+            }
+            out.write_all(&psychic.fitness.to_le_bytes())?;
+            out.write_all(&[psychic.stand_in as u8])?;
+        }
+        write_evolution(&mut out, &self.evolution)?;
+        out.write_all(&self.best_fitness.to_le_bytes())?;
+        out.write_all(&(self.archive.len() as u32).to_le_bytes())?;
+        for behaviour in self.archive.iter(){
+            out.write_all(&behaviour.final_position.x.to_le_bytes())?;
+            out.write_all(&behaviour.final_position.y.to_le_bytes())?;
+            out.write_all(&(behaviour.visited.len() as u32).to_le_bytes())?;
+            for tile in behaviour.visited.iter(){
+                out.write_all(&(*tile as u32).to_le_bytes())?;
+            }
+            out.write_all(&(behaviour.actions.len() as u32).to_le_bytes())?;
+            for share in behaviour.actions.iter(){
+                out.write_all(&share.to_le_bytes())?;
+            }
+        }
+        out.write_all(&self.speciation.threshold.to_le_bytes())?;
+        out.write_all(&(self.speciation.next_id as u32).to_le_bytes())?;
+        out.write_all(&(self.speciation.clades.len() as u32).to_le_bytes())?;
+        for clade in self.speciation.clades.iter(){
+            for number in [clade.id, clade.members, clade.stagnant_for, clade.offspring] {
+                out.write_all(&(number as u32).to_le_bytes())?;
+            }
+            out.write_all(&clade.best_fitness.to_le_bytes())?;
+            out.write_all(&clade.mean_fitness.to_le_bytes())?;
+            write_net(&mut out, &clade.representative)?;
+        }
+        out.write_all(&(self.champions.len() as u32).to_le_bytes())?;
+        for champion in self.champions.iter(){
+            out.write_all(&(champion.generation as u32).to_le_bytes())?;
+            out.write_all(&champion.fitness.to_le_bytes())?;
+            out.write_all(&(champion.trials.len() as u32).to_le_bytes())?;
+            for score in champion.trials.iter(){
+                out.write_all(&score.to_le_bytes())?;
+            }
+            write_net(&mut out, &champion.brain)?;
+            out.write_all(&(champion.action_choices.len() as u32).to_le_bytes())?;
+            for choice in champion.action_choices.iter(){
+                out.write_all(&axiom_to_bytes(*choice))?;
+            }
+            out.write_all(&[species_to_byte(champion.species)])?;
+        }
+        out.write_all(&(self.rivals.len() as u32).to_le_bytes())?;
+        for (population, archive) in self.rivals.iter(){
+            out.write_all(&[species_to_byte(*population)])?;
+            out.write_all(&(archive.len() as u32).to_le_bytes())?;
+            for brain in archive.iter(){
+                write_net(&mut out, brain)?;
+            }
+        }
+        match self.energy {
+            None => out.write_all(&[0])?,
+            Some(energy) => {
+                out.write_all(&[1])?;
+                for number in [energy.capacity, energy.move_cost, energy.paint_cost, energy.recharge] {
+                    out.write_all(&number.to_le_bytes())?;
+                }
+                out.write_all(&[exhaustion_to_byte(energy.exhaustion)])?;
+            },
+        }
+        out.write_all(&(self.elite.len() as u32).to_le_bytes())?;
+        for member in self.elite.iter(){
+            out.write_all(&[species_to_byte(member.population)])?;
+            out.write_all(&(member.objectives.len() as u32).to_le_bytes())?;
+            for objective in member.objectives.iter(){
+                out.write_all(&objective.to_le_bytes())?;
+            }
+            out.write_all(&(member.front as u32).to_le_bytes())?;
+            out.write_all(&member.crowding.to_le_bytes())?;
+            write_net(&mut out, &member.brain)?;
+        }
+        out.write_all(&self.rng.get_seed())?; // And where its stream is at, the seed alone would start it over.
+        out.write_all(&self.rng.get_stream().to_le_bytes())?;
+        out.write_all(&self.rng.get_word_pos().to_le_bytes())?;
+        out.write_all(&(self.history.len() as u32).to_le_bytes())?;
+        for stats in self.history.iter(){
+            out.write_all(&(stats.generation as u32).to_le_bytes())?;
+            out.write_all(&stats.best_fitness.to_le_bytes())?;
+            out.write_all(&stats.mean_fitness.to_le_bytes())?;
+        }
+        out.write_all(&(self.population.len() as u32).to_le_bytes())?;
+        let population: Vec<u8> = self.population.iter().map(|s| species_to_byte(*s)).collect();
+        out.write_all(&population)?;
+        out.flush()?;
+        drop(out);
+        fs::rename(temporary, path)
+    }
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        let mut reader = ByteReader{bytes: &bytes, cursor: 0};
+        if reader.take(4)? != MAGIC {
+            return Err(invalid("not a checkpoint file"));
+        }
+        if reader.u8()? != VERSION {
+            return Err(invalid("unsupported checkpoint version"));
+        }
+        let generation = reader.u32()? as usize;
+        let width = reader.u32()?;
+        let height = reader.u32()?;
+        let topology = byte_to_topology(reader.u8()?)?;
+        let out_of_bounds = byte_to_out_of_bounds(reader.u8()?)?;
+        let sense_shape = byte_to_shape(reader.u8()?)?;
+        let sense_centre = reader.u8()? != 0;
+        let tiles = reader.take(tile_count(width, height)?)?.iter().map(|byte| byte_to_species(*byte)).collect::<io::Result<Vec<_>>>()?;
+        let mut psychics = Vec::new();
+        for _p in 0..reader.u32()? {
+            let position = (reader.u32()?, reader.u32()?);
+            let brain = read_net(&mut reader)?;
+            let mut action_choices = Vec::new();
+            for _c in 0..reader.u32()? {
+                action_choices.push(read_axiom(&mut reader)?);
+            }
+            let mut actions_chosen = Vec::new();
+            for _a in 0..reader.u32()? {
+                actions_chosen.push((reader.i32()?, reader.i32()?));
+            }
+            let (fitness, stand_in) = (reader.f32()?, reader.u8()? != 0);
+            psychics.push(SavedPsychic{position, brain, action_choices, actions_chosen, fitness, stand_in});
+        }
+        let evolution = read_evolution(&mut reader)?;
+        let best_fitness = reader.f32()?;
+        let mut archive = Vec::new();
+        for _b in 0..reader.u32()? {
+            let final_position = Vec2::new(reader.f32()?, reader.f32()?);
+            let mut visited = Vec::new();
+            for _v in 0..reader.u32()? {
+                visited.push(reader.u32()? as usize);
+            }
+            let mut actions = Vec::new();
+            for _a in 0..reader.u32()? {
+                actions.push(reader.f32()?);
+            }
+            archive.push(Behaviour{final_position, visited, actions});
+        }
+        let mut speciation = Speciation{threshold: reader.f64()?, next_id: reader.u32()? as usize, ..default()};
+        for _c in 0..reader.u32()? {
+            let (id, members, stagnant_for, offspring) = (reader.u32()? as usize, reader.u32()? as usize, reader.u32()? as usize, reader.u32()? as usize);
+            let (best_fitness, mean_fitness) = (reader.f32()?, reader.f32()?);
+            let representative = read_net(&mut reader)?;
+            speciation.clades.push(Clade{id, representative, members, best_fitness, mean_fitness, stagnant_for, offspring});
+        }
+        let mut champions = Vec::new();
+        for _c in 0..reader.u32()? {
+            let (generation, fitness) = (reader.u32()? as usize, reader.f32()?);
+            let mut trials = Vec::new();
+            for _r in 0..reader.u32()? {
+                trials.push(reader.f32()?);
+            }
+            let brain = read_net(&mut reader)?;
+            let mut action_choices = Vec::new();
+            for _a in 0..reader.u32()? {
+                action_choices.push(read_axiom(&mut reader)?);
+            }
+            let species = byte_to_species(reader.u8()?)?;
+            champions.push(Champion{brain, action_choices, species, generation, fitness, trials});
+        }
+        let mut rivals = Vec::new();
+        for _r in 0..reader.u32()? {
+            let population = byte_to_species(reader.u8()?)?;
+            let mut archive = Vec::new();
+            for _b in 0..reader.u32()? {
+                archive.push(read_net(&mut reader)?);
+            }
+            rivals.push((population, archive));
+        }
+        let mut energy = None;
+        if reader.u8()? != 0 {
+            let (capacity, move_cost, paint_cost, recharge) = (reader.f32()?, reader.f32()?, reader.f32()?, reader.f32()?);
+            energy = Some(Energy{capacity, move_cost, paint_cost, recharge, exhaustion: byte_to_exhaustion(reader.u8()?)?});
+        }
+        let mut elite = Vec::new();
+        for _m in 0..reader.u32()? {
+            let population = byte_to_species(reader.u8()?)?;
+            let mut objectives = Vec::new();
+            for _o in 0..reader.u32()? {
+                objectives.push(reader.f32()?);
+            }
+            let (front, crowding) = (reader.u32()? as usize, reader.f32()?);
+            elite.push(EliteMember{brain: read_net(&mut reader)?, objectives, population, front, crowding});
+        }
+        let mut rng = ChaCha12Rng::from_seed(reader.take(32)?.try_into().unwrap());
+        rng.set_stream(reader.u64()?);
+        rng.set_word_pos(reader.u128()?);
+        let mut history = Vec::new();
+        for _s in 0..reader.u32()? {
+            history.push(GenerationStats{generation: reader.u32()? as usize, best_fitness: reader.f32()?, mean_fitness: reader.f32()?});
+        }
+        let count = reader.u32()? as usize;
+        let population = reader.take(count)?.iter().map(|byte| byte_to_species(*byte)).collect::<io::Result<Vec<_>>>()?;
+        let checkpoint = Self{generation, width, height, topology, out_of_bounds, sense_shape, sense_centre, tiles, psychics, evolution, best_fitness, archive, speciation, champions, rivals, energy, elite, rng, history, population};
+        checkpoint.check_positions()?;
+        checkpoint.check_brains()?;
+        Ok(checkpoint)
+    }
+    pub fn senses(&self) -> usize { // What the brains of this run take as inputs.
+        let mut map = Map::new(0, 0);
+        (map.out_of_bounds, map.sense_shape, map.sense_centre) = (self.out_of_bounds, self.sense_shape, self.sense_centre);
+        map.rival_senses = self.tiles.contains(&Species::Hunter);
+        map.teams = self.tiles.contains(&Species::Red);
+        map.energy = self.energy;
+        sense_count(&map)
+    }
+    fn check_positions(&self) -> io::Result<()> { // Rather than indexing past the tiles when resuming.
+        for psychic in self.psychics.iter(){
+            let (x, y) = psychic.position;
+            if x >= self.width || y >= self.height {
+                return Err(invalid(&format!("a Psychic starts at ({x}, {y}), outside of a {}x{} arena", self.width, self.height)));
+            }
+        }
+        Ok(())
+    }
+    fn check_brains(&self) -> io::Result<()> { // Rather than a Net panicking on its first turn.
+        let senses = self.senses();
+        let brains = self.psychics.iter().map(|p| &p.brain)
+            .chain(self.champions.iter().map(|c| &c.brain))
+            .chain(self.speciation.clades.iter().map(|c| &c.representative))
+            .chain(self.rivals.iter().flat_map(|(_, archive)| archive.iter()))
+            .chain(self.elite.iter().map(|m| &m.brain));
+        for brain in brains {
+            if brain.shape()[0] != senses {
+                return Err(invalid(&format!("a brain takes {} senses, but this arena gives {senses}", brain.shape()[0])));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_net( // Full precision, unlike the floats of a replay: a resumed brain must be the very same.
+    out: &mut impl Write,
+    net: &Net,
+) -> io::Result<()> {
+    let shape = net.shape();
+    out.write_all(&(shape.len() as u32).to_le_bytes())?;
+    for size in shape.iter(){
+        out.write_all(&(*size as u32).to_le_bytes())?;
+    }
+    for layer in net.weights(){
+        for node in layer.iter(){
+            for weight in node.iter(){
+                out.write_all(&weight.to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_net(
+    reader: &mut ByteReader,
+) -> io::Result<Net> {
+    let mut shape = Vec::new();
+    for _s in 0..reader.u32()? {
+        shape.push(reader.u32()? as usize);
+    }
+    if shape.len() < 2 {
+        return Err(invalid("a brain needs at least two layers"));
+    }
+    let mut layers = Vec::with_capacity(shape.len() - 1);
+    for sizes in shape.windows(2){
+        let mut nodes = Vec::with_capacity(reader.capacity(sizes[1], 8));
+        for _n in 0..sizes[1] {
+            let mut node = Vec::with_capacity(reader.capacity(sizes[0] + 1, 8));
+            for _w in 0..sizes[0] + 1 { // The bias comes first.
+                node.push(reader.f64()?);
+            }
+            nodes.push(node);
+        }
+        layers.push(nodes);
+    }
+    Ok(Net::from_weights(shape[0], layers))
+}
+
+fn write_evolution(
+    out: &mut impl Write,
+    settings: &EvolutionSettings,
+) -> io::Result<()> {
+    out.write_all(&settings.mutation_rate.to_le_bytes())?;
+    out.write_all(&settings.mutation_strength.to_le_bytes())?;
+    out.write_all(&[selection_to_byte(settings.selection)])?;
+    for weight in [settings.fitness.paint, settings.fitness.wander, settings.fitness.variety, settings.fitness.painter, settings.novelty_weight, settings.fitness.catch, settings.fitness.evade, settings.fitness.territory] {
+        out.write_all(&weight.to_le_bytes())?;
+    }
+    for number in [settings.target_species, settings.turns_per_frame, settings.max_turn_number] {
+        out.write_all(&(number as u32).to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn read_evolution(
+    reader: &mut ByteReader,
+) -> io::Result<EvolutionSettings> {
+    let mutation_rate = reader.f64()?;
+    let mutation_strength = reader.f64()?;
+    let selection = byte_to_selection(reader.u8()?)?;
+    let (paint, wander, variety, painter) = (reader.f32()?, reader.f32()?, reader.f32()?, reader.f32()?);
+    let novelty_weight = reader.f32()?;
+    let fitness = FitnessWeights{paint, wander, variety, painter, catch: reader.f32()?, evade: reader.f32()?, territory: reader.f32()?};
+    let target_species = reader.u32()? as usize;
+    let turns_per_frame = reader.u32()? as usize;
+    let max_turn_number = reader.u32()? as usize;
+    Ok(EvolutionSettings{mutation_rate, mutation_strength, selection, fitness, novelty_weight, target_species, turns_per_frame, max_turn_number})
+}
+
+fn exhaustion_to_byte(exhaustion: Exhaustion) -> u8 {
+    match exhaustion {
+        Exhaustion::Freeze => 0,
+        Exhaustion::Die => 1,
+    }
+}
+
+fn byte_to_exhaustion(byte: u8) -> io::Result<Exhaustion> {
+    match byte {
+        0 => Ok(Exhaustion::Freeze),
+        1 => Ok(Exhaustion::Die),
+        _ => Err(invalid("unknown exhaustion rule")),
+    }
+}
+
+fn topology_to_byte(topology: Topology) -> u8 {
+    match topology {
+        Topology::Bounded => 0,
+        Topology::Toroidal => 1,
+    }
+}
+
+fn byte_to_topology(byte: u8) -> io::Result<Topology> {
+    match byte {
+        0 => Ok(Topology::Bounded),
+        1 => Ok(Topology::Toroidal),
+        _ => Err(invalid("unknown topology")),
+    }
+}
+
+fn out_of_bounds_to_byte(out_of_bounds: OutOfBounds) -> u8 {
+    match out_of_bounds {
+        OutOfBounds::Wall => 0,
+        OutOfBounds::Empty => 1,
+        OutOfBounds::Wrap => 2,
+    }
+}
+
+fn byte_to_out_of_bounds(byte: u8) -> io::Result<OutOfBounds> {
+    match byte {
+        0 => Ok(OutOfBounds::Wall),
+        1 => Ok(OutOfBounds::Empty),
+        2 => Ok(OutOfBounds::Wrap),
+        _ => Err(invalid("unknown out of bounds rule")),
+    }
+}
+
+fn shape_to_byte(shape: NeighbourhoodShape) -> u8 {
+    match shape {
+        NeighbourhoodShape::Moore => 0,
+        NeighbourhoodShape::VonNeumann => 1,
+    }
+}
+
+fn byte_to_shape(byte: u8) -> io::Result<NeighbourhoodShape> {
+    match byte {
+        0 => Ok(NeighbourhoodShape::Moore),
+        1 => Ok(NeighbourhoodShape::VonNeumann),
+        _ => Err(invalid("unknown neighbourhood shape")),
+    }
+}
+
+fn selection_to_byte(selection: Selection) -> u8 {
+    match selection {
+        Selection::Roulette => 0,
+        Selection::Tournament => 1,
+        Selection::Truncation => 2,
+        Selection::Pareto => 3,
+    }
+}
+
+fn byte_to_selection(byte: u8) -> io::Result<Selection> {
+    match byte {
+        0 => Ok(Selection::Roulette),
+        1 => Ok(Selection::Tournament),
+        2 => Ok(Selection::Truncation),
+        3 => Ok(Selection::Pareto),
+        _ => Err(invalid("unknown selection")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    fn checkpoint() -> Checkpoint {
+        let mut checkpoint = Checkpoint{
+            generation: 12, width: 3, height: 2,
+            topology: Topology::Toroidal, out_of_bounds: OutOfBounds::Wrap, sense_shape: NeighbourhoodShape::VonNeumann, sense_centre: true,
+            tiles: vec![Species::Wall, Species::Psychic, Species::Hunter, Species::Beacon, Species::Nothing, Species::Caught],
+            psychics: Vec::new(),
+            evolution: EvolutionSettings{selection: Selection::Tournament, novelty_weight: 0.25, ..default()},
+            best_fitness: 321.,
+            archive: vec![Behaviour{final_position: Vec2::new(0.5, 1.), visited: vec![1, 4], actions: vec![0.75, 0.25]}],
+            speciation: Speciation{threshold: 0.4, next_id: 3, ..default()},
+            champions: Vec::new(),
+            rivals: Vec::new(),
+            energy: Some(Energy::new(30., Exhaustion::Die)),
+            elite: Vec::new(),
+            rng: ChaCha12Rng::seed_from_u64(7),
+            history: vec![GenerationStats{generation: 11, best_fitness: 321., mean_fitness: 40.5}],
+            population: vec![Species::Beacon, Species::Psychic, Species::Hunter],
+        };
+        let senses = checkpoint.senses();
+        let mut rng = rand::thread_rng();
+        let mut brain = || Net::new(vec![senses, 4, 5], &mut rng);
+        let choices = vec![Axiom::Move{dx: 0, dy: 1}, Axiom::Tag];
+        checkpoint.psychics = vec![SavedPsychic{position: (1, 0), brain: brain(), action_choices: choices.clone(), actions_chosen: vec![(0, 1), (-1, 0)], fitness: 0.01, stand_in: true}];
+        checkpoint.speciation.clades = vec![Clade{id: 2, representative: brain(), members: 1, best_fitness: 9., mean_fitness: 4., stagnant_for: 1, offspring: 1}];
+        checkpoint.champions = vec![Champion{brain: brain(), action_choices: choices, species: Species::Hunter, generation: 10, fitness: 50., trials: vec![40., 60.]}];
+        checkpoint.rivals = vec![(Species::Hunter, vec![brain()])];
+        checkpoint.elite = vec![EliteMember{brain: brain(), objectives: vec![3., 40., 2., 7.], population: Species::Psychic, front: 0, crowding: f32::INFINITY}];
+        checkpoint
+    }
+
+    fn save_and_load(checkpoint: &Checkpoint, name: &str) -> io::Result<Checkpoint> {
+        let path = std::env::temp_dir().join(format!("tgfp_{name}_{}.tgfc", std::process::id()));
+        checkpoint.save(&path)?;
+        let loaded = Checkpoint::load(&path);
+        fs::remove_file(&path)?;
+        loaded
+    }
+
+    #[test]
+    fn round_trip() {
+        let saved = checkpoint();
+        let loaded = save_and_load(&saved, "checkpoint_test").unwrap();
+        assert_eq!((loaded.generation, loaded.width, loaded.height), (12, 3, 2));
+        assert_eq!((loaded.topology, loaded.out_of_bounds, loaded.sense_shape, loaded.sense_centre), (saved.topology, saved.out_of_bounds, saved.sense_shape, saved.sense_centre));
+        assert_eq!(loaded.tiles, saved.tiles);
+        assert_eq!(loaded.psychics[0].position, saved.psychics[0].position);
+        assert_eq!(loaded.psychics[0].brain.weights(), saved.psychics[0].brain.weights());
+        assert_eq!(loaded.psychics[0].action_choices, saved.psychics[0].action_choices);
+        let (a, b) = (&loaded.psychics[0], &saved.psychics[0]);
+        assert_eq!((&a.actions_chosen, a.fitness, a.stand_in), (&b.actions_chosen, b.fitness, b.stand_in));
+        assert_eq!(loaded.evolution, saved.evolution);
+        assert_eq!(loaded.best_fitness, saved.best_fitness);
+        assert_eq!(loaded.archive[0].visited, saved.archive[0].visited);
+        assert_eq!(loaded.archive[0].actions, saved.archive[0].actions);
+        assert_eq!((loaded.speciation.threshold, loaded.speciation.next_id), (0.4, 3));
+        assert_eq!(loaded.speciation.clades[0].representative.weights(), saved.speciation.clades[0].representative.weights());
+        let (a, b) = (&loaded.champions[0], &saved.champions[0]);
+        assert_eq!((a.species, a.generation, a.fitness, &a.trials, &a.action_choices), (b.species, b.generation, b.fitness, &b.trials, &b.action_choices));
+        assert_eq!(loaded.rivals[0].0, Species::Hunter);
+        assert_eq!(loaded.rivals[0].1[0].weights(), saved.rivals[0].1[0].weights());
+        assert_eq!(loaded.energy, saved.energy);
+        let (a, b) = (&loaded.elite[0], &saved.elite[0]);
+        assert_eq!((&a.objectives, a.population, a.front, a.crowding), (&b.objectives, b.population, b.front, b.crowding));
+        assert_eq!(a.brain.weights(), b.brain.weights());
+        assert_eq!(loaded.history, saved.history);
+        assert_eq!(loaded.population, saved.population);
+    }
+
+    #[test]
+    fn the_dice_carry_on_where_they_were() {
+        let mut saved = checkpoint();
+        let _rolled: Vec<u64> = (0..5).map(|_| saved.rng.gen()).collect(); // Somewhere in the middle of its stream.
+        let mut dice = saved.rng.clone();
+        let mut loaded = save_and_load(&saved, "dice_test").unwrap().rng;
+        let expected: Vec<u64> = (0..20).map(|_| dice.gen()).collect();
+        let rolled: Vec<u64> = (0..20).map(|_| loaded.gen()).collect();
+        assert_eq!(rolled, expected);
+    }
+
+    #[test]
+    fn rejects_brains_of_the_wrong_size() {
+        let mut saved = checkpoint();
+        saved.energy = None; // The brains still expect to sense it.
+        let error = save_and_load(&saved, "mismatch_test").err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_psychics_off_the_map() {
+        let mut saved = checkpoint();
+        saved.psychics[0].position = (1, 2); // The arena is only two tiles high.
+        let error = save_and_load(&saved, "outside_test").err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use rand::seq::IteratorRandom;
+
+use crate::{config::Config, hall_of_fame::induct_champion, map::Species, nn::Net, psychics::{Soul, Trace}, simulation::{GenerationFinished, TrainingRng, evolve_generation}};
+
+pub struct CoevolutionPlugin;
+
+impl Plugin for CoevolutionPlugin {
+    fn build(&self, app: &mut App) {
+        let config = app.world.get_resource::<Config>().cloned().unwrap_or_default();
+        if config.hunters == 0 {
+            return; // One population, nothing to pit against anything.
+        }
+        app.insert_resource(Rivalry{archives: Vec::new(), share: config.archived_rivals});
+        app.add_systems(Update, field_rivals.after(evolve_generation).before(induct_champion)); // Both roll the training dice, always in the same order.
+    }
+}
+
+const ARCHIVE_SIZE: usize = 20; // Past champions kept per population, the oldest are forgotten first.
+
+#[derive(Resource, Clone)]
+pub struct Rivalry { // The arms race between the Psychics and the Hunters.
+    pub archives: Vec<(Species, VecDeque<Net>)>, // The best brain of every generation, per population.
+    pub share: f32, // How much of each population is played by its archive, 0 for always facing the current rivals.
+}
+
+impl Rivalry {
+    pub fn archive(&mut self, population: Species) -> &mut VecDeque<Net> {
+        if let Some(index) = self.archives.iter().position(|(s, _)| *s == population) {
+            return &mut self.archives[index].1;
+        }
+        self.archives.push((population, VecDeque::new()));
+        &mut self.archives.last_mut().unwrap().1
+    }
+}
+
+pub fn field_rivals( // Archives each population's best, then hands a few of the next generation's slots over to past champions.
+    mut finished: EventReader<GenerationFinished>,
+    mut rivalry: ResMut<Rivalry>,
+    mut psychics: Query<(&mut Soul, &Trace)>,
+    mut rng: ResMut<TrainingRng>,
+){
+    let Some(event) = finished.read().last() else { return };
+    let mut report = Vec::new();
+    for population in [Species::Psychic, Species::Hunter] {
+        let members = || psychics.iter().filter(|(_, trace)| trace.original_species == population);
+        let caught = members().filter(|(_, trace)| trace.shipped_identity.last() == Some(&Species::Caught)).count();
+        let best = members() // Stand-ins played a brain which is archived already.
+            .filter(|(soul, _)| !soul.stand_in)
+            .filter_map(|(_, trace)| Some((trace.shipped_fitness, trace.shipped_brain.clone()?)))
+            .max_by(|a, b| a.0.total_cmp(&b.0));
+        let count = members().count();
+        match population {
+            Species::Hunter => report.push(format!("{count} Hunters, best {:.0}", best.as_ref().map_or(0., |b| b.0))),
+            _ => report.push(format!("{count} Psychics, best {:.0}, {caught} caught", best.as_ref().map_or(0., |b| b.0))),
+        }
+        if event.generation == 0 {
+            continue; // Generation 0 is the empty one evolved on startup.
+        }
+        let archive = rivalry.archive(population);
+        if let Some((_, brain)) = best {
+            archive.push_back(brain);
+            if archive.len() > ARCHIVE_SIZE {
+                archive.pop_front();
+            }
+        }
+    }
+    info!("Generation {}: {}.", event.generation, report.join(", "));
+    let rng = &mut rng.0;
+    for (mut soul, _) in psychics.iter_mut(){
+        soul.stand_in = false;
+    }
+    for (population, archive) in rivalry.archives.iter(){
+        let slots = psychics.iter_mut().filter(|(_, trace)| trace.original_species == *population).collect::<Vec<_>>();
+        let count = (slots.len() as f32 * rivalry.share).round() as usize;
+        for (mut soul, _) in slots.into_iter().choose_multiple(rng, count){
+            let Some(champion) = archive.iter().choose(rng) else { break };
+            if champion.shape() != soul.nn.shape() {
+                continue; // Archived before the senses changed.
+            }
+            soul.nn = champion.clone();
+            soul.stand_in = true;
+        }
+    }
+}
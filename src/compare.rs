@@ -0,0 +1,177 @@
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+
+use crate::{config::Config, map::Map, replay::Replay, theatre::{TILE_SIZE, TheatreSettings, replay_actors}, SpriteSheetHandle};
+
+pub struct ComparePlugin;
+
+impl Plugin for ComparePlugin {
+    fn build(&self, app: &mut App) {
+        let config = app.world.get_resource::<Config>().cloned().unwrap_or_default();
+        let Some(path) = config.compare else { return };
+        app.insert_resource(Comparison{path, layout: CompareLayout::SideBySide, generation: 0, width: 0, height: 0, turns: 0});
+        app.add_systems(Startup, (load_comparison, draw_compare_label));
+        app.add_systems(Update, (switch_layout, place_comparison, stretch_timeline).chain());
+    }
+}
+
+const GAP: f32 = 2.; // In tiles, between the two arenas.
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CompareLayout {
+    SideBySide, // The compared replay gets its own arena, right of the main one.
+    Overlay, // Both on the same arena, the compared one faded.
+}
+
+#[derive(Resource)]
+pub struct Comparison { // A second replay played in lock-step with whatever the theatre is showing.
+    pub path: PathBuf,
+    pub layout: CompareLayout,
+    pub generation: usize,
+    pub width: u32,
+    pub height: u32,
+    pub turns: usize,
+}
+
+impl Comparison {
+    pub fn offset(&self, map: &Map) -> Vec3 { // Where the compared arena starts.
+        match self.layout {
+            CompareLayout::SideBySide => Vec3::new((map.width as f32 + GAP) * TILE_SIZE, 0., 0.),
+            CompareLayout::Overlay => Vec3::new(0., 0., 0.5), // Just above, so it is not hidden.
+        }
+    }
+    pub fn extra_width(&self, map: &Map) -> f32 { // How much room the compared arena takes right of the main one.
+        match self.layout {
+            CompareLayout::SideBySide => (GAP + self.width as f32) * TILE_SIZE,
+            CompareLayout::Overlay => (self.width as f32 - map.width as f32).max(0.) * TILE_SIZE,
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct Compared { // Theatre creatures belonging to the compared replay.
+    pub offset: Vec3,
+    pub faded: bool,
+}
+
+#[derive(Component)]
+pub struct CompareBackground{
+    border: f32,
+}
+
+#[derive(Component)]
+pub struct CompareLabel;
+
+fn load_comparison(
+    mut commands: Commands,
+    mut comparison: ResMut<Comparison>,
+    tex_handle: Res<SpriteSheetHandle>,
+){
+    let replay = match Replay::load(&comparison.path) {
+        Ok(replay) => replay,
+        Err(e) => {
+            warn!("Could not open the replay to compare with at {}: {e}", comparison.path.display());
+            return;
+        }
+    };
+    for actor in replay_actors(&replay, &tex_handle){
+        commands.spawn((actor, Compared{offset: Vec3::ZERO, faded: false}));
+    }
+    for (color, border) in [(Color::WHITE, TILE_SIZE), (Color::BLACK, 0.)] { // Same frame as the main arena.
+        commands.spawn((SpriteBundle {
+            sprite: Sprite {
+                color,
+                custom_size: Some(Vec2::new(replay.width as f32 * TILE_SIZE + border, replay.height as f32 * TILE_SIZE + border)),
+                ..default()
+            },
+            ..default()
+        }, CompareBackground{border}));
+    }
+    comparison.generation = replay.generation;
+    (comparison.width, comparison.height) = (replay.width, replay.height);
+    comparison.turns = replay.turn_count();
+    info!("Comparing with generation {} ({}).", replay.generation, comparison.path.display());
+}
+
+fn draw_compare_label(
+    mut commands: Commands,
+){
+    commands.spawn((TextBundle::from_section(
+        "",
+        TextStyle {
+            font_size: 16.,
+            color: Color::WHITE,
+            ..default()
+        },
+    ).with_style(Style {
+        position_type: PositionType::Absolute,
+        top: Val::Px(22.),
+        left: Val::Px(4.),
+        ..default()
+    }), CompareLabel));
+}
+
+fn switch_layout( // V swaps between side by side and overlaid.
+    keys: Res<Input<KeyCode>>,
+    mut comparison: ResMut<Comparison>,
+    mut label: Query<&mut Text, With<CompareLabel>>,
+){
+    if keys.just_pressed(KeyCode::V) {
+        comparison.layout = match comparison.layout {
+            CompareLayout::SideBySide => CompareLayout::Overlay,
+            CompareLayout::Overlay => CompareLayout::SideBySide,
+        };
+    }
+    if !comparison.is_changed() {
+        return;
+    }
+    for mut text in label.iter_mut(){
+        text.sections[0].value = match comparison.layout {
+            CompareLayout::SideBySide => format!("Right: generation {}", comparison.generation),
+            CompareLayout::Overlay => format!("Faded: generation {}", comparison.generation),
+        };
+    }
+}
+
+fn place_comparison( // Keeps the compared arena next to the main one, even when that one changes size.
+    comparison: Res<Comparison>,
+    map: Res<Map>,
+    mut actors: Query<&mut Compared>,
+    mut background: Query<(&mut Transform, &mut Visibility, &CompareBackground)>,
+    mut config: ResMut<TheatreSettings>,
+){
+    let offset = comparison.offset(&map);
+    let faded = comparison.layout == CompareLayout::Overlay;
+    let mut moved = false;
+    for mut actor in actors.iter_mut(){
+        if actor.offset != offset || actor.faded != faded {
+            (actor.offset, actor.faded) = (offset, faded);
+            moved = true;
+        }
+    }
+    if moved {
+        config.requested_turn = Some(config.current_turn); // Jump everyone to their new spot.
+    }
+    let centre = Vec3::new(comparison.width as f32 * TILE_SIZE / 2., comparison.height as f32 * TILE_SIZE / 2., 0.);
+    for (mut transform, mut visibility, frame) in background.iter_mut(){
+        let wanted = if faded { Visibility::Hidden } else { Visibility::Visible };
+        if *visibility != wanted {
+            *visibility = wanted;
+        }
+        let depth = if frame.border > 0. { -0.2 } else { -0.1 }; // Behind the creatures, the white frame behind the black.
+        let translation = centre + offset.truncate().extend(depth);
+        if transform.translation != translation {
+            transform.translation = translation;
+        }
+    }
+}
+
+fn stretch_timeline( // So the longer of the two replays can be watched to its end.
+    comparison: Res<Comparison>,
+    mut config: ResMut<TheatreSettings>,
+){
+    if config.max_turn_number < comparison.turns {
+        config.max_turn_number = comparison.turns;
+    }
+}
@@ -0,0 +1,206 @@
+use std::{path::{Path, PathBuf}, str::FromStr};
+
+use bevy::prelude::*;
+
+use crate::{export::{ExportFormat, DEFAULT_FRAME_DELAY}, map::{room_for, Map, NeighbourhoodShape, OutOfBounds, Species, Topology}, energy::Exhaustion, replay::RecordPolicy, scripts::ScriptRule, simulation::Selection};
+
+pub const DEFAULT_ARENA_WIDTH: u32 = 45;
+pub const DEFAULT_ARENA_HEIGHT: u32 = 45;
+
+#[derive(Resource, Clone)]
+pub struct Config { // Everything that can be tweaked from the command line, e.g. "cargo run -- --width 15 --height 15"
+    pub arena_width: u32,
+    pub arena_height: u32,
+    pub topology: Topology,
+    pub out_of_bounds: OutOfBounds,
+    pub sense_shape: NeighbourhoodShape,
+    pub sense_centre: bool,
+    pub record_policy: RecordPolicy,
+    pub replay_directory: PathBuf,
+    pub replay: Option<PathBuf>, // If set, only watch this saved replay instead of training.
+    pub compare: Option<PathBuf>, // A second replay to play in lock-step with the first, or with training.
+    pub record_decisions: bool,
+    pub headless: bool, // Train without a window, e.g. on a server.
+    pub generations: Option<usize>, // Stop training after this many.
+    pub export: Option<PathBuf>, // If set, only turn this saved replay into pictures, then quit.
+    pub export_format: ExportFormat,
+    pub export_to: Option<PathBuf>,
+    pub export_recorded: bool, // Also make a GIF of every replay recorded while training.
+    pub frame_delay: u32, // Milliseconds between turns in exported GIFs.
+    pub novelty_weight: f32, // 0 for plain fitness, 1 for pure novelty search.
+    pub selection: Selection,
+    pub species: usize, // Roughly how many species to keep, 0 for no speciation.
+    pub islands: usize, // Populations trained side by side, each on its own thread.
+    pub island_args: Vec<String>, // Extra flags for each island, in order.
+    pub migration_interval: usize, // In generations.
+    pub migrants: usize, // How many of its fittest an island sends to the next one at each migration.
+    pub checkpoint: PathBuf, // Where the whole training run gets saved.
+    pub checkpoint_interval: Option<usize>, // In generations. Also saved when quitting. None saves nothing.
+    pub resume: Option<PathBuf>, // A checkpoint to carry on training from.
+    pub hall_size: usize, // How many champions the hall of fame keeps, 0 for none.
+    pub reevaluation_interval: usize, // In generations. 0 never plays the champions again.
+    pub hunters: usize, // Psychics of a second population, evolved to catch the first one. 0 for none.
+    pub archived_rivals: f32, // Share of each population played by its past champions instead, so the other one cannot just beat the latest tactic.
+    pub paintball: bool, // Two factions of Psychics, each painting the walls in its colour.
+    pub scripts: Vec<ScriptRule>, // What the Hylics do on their own, per species.
+    pub energy: f32, // What each Psychic starts a generation with, 0 for unlimited.
+    pub exhaustion: Exhaustion,
+    pub seed: Option<u64>, // Where training's dice start. None picks one, and logs it.
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            arena_width: DEFAULT_ARENA_WIDTH,
+            arena_height: DEFAULT_ARENA_HEIGHT,
+            topology: Topology::Bounded,
+            out_of_bounds: OutOfBounds::Wall,
+            sense_shape: NeighbourhoodShape::Moore,
+            sense_centre: false,
+            record_policy: RecordPolicy::Never,
+            replay_directory: PathBuf::from("replays"),
+            replay: None,
+            compare: None,
+            record_decisions: true,
+            headless: false,
+            generations: None,
+            export: None,
+            export_format: ExportFormat::Gif,
+            export_to: None,
+            export_recorded: false,
+            frame_delay: DEFAULT_FRAME_DELAY,
+            novelty_weight: 0.,
+            selection: Selection::Roulette,
+            species: 0,
+            islands: 1,
+            island_args: Vec::new(),
+            migration_interval: 10,
+            migrants: 2,
+            checkpoint: PathBuf::from("checkpoints/latest.tgfc"),
+            checkpoint_interval: None,
+            resume: None,
+            hall_size: 10,
+            reevaluation_interval: 10,
+            hunters: 0,
+            archived_rivals: 0.,
+            paintball: false,
+            scripts: Vec::new(),
+            energy: 0.,
+            exhaustion: Exhaustion::Freeze,
+            seed: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_args() -> Self {
+        let mut config = Config::default();
+        config.apply(std::env::args().skip(1));
+        assert!(config.island_args.len() <= config.islands, "There are {} --island but only {} --islands.", config.island_args.len(), config.islands);
+        for args in config.island_args.iter(){
+            for flag in ["--islands", "--island", "--headless", "--resume"] {
+                assert!(!args.split_whitespace().any(|a| a == flag), "{flag} is for all the islands at once, it cannot go in --island \"{args}\".");
+            }
+        }
+        config.check();
+        assert!(config.islands == 1 || config.headless, "The islands only run headless.");
+        config
+    }
+    pub fn for_island(&self, index: usize) -> Self { // The shared settings, then whatever was given to this island's --island.
+        let mut island = self.clone();
+        island.seed = self.seed.map(|seed| seed.wrapping_add(index as u64)); // Not all rolling the same dice.
+        if let Some(args) = self.island_args.get(index) {
+            island.apply(args.split_whitespace().map(String::from));
+            island.check();
+        }
+        let folder = format!("island_{index}"); // Each one records its own replays and checkpoints.
+        island.replay_directory = island.replay_directory.join(&folder);
+        island.checkpoint = in_folder(&island.checkpoint, &folder);
+        island.resume = island.resume.as_deref().map(|path| in_folder(path, &folder));
+        island
+    }
+    fn apply(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ){
+        while let Some(flag) = args.next() {
+            match flag.as_str() {
+                "--width" => self.arena_width = parse_value(&flag, args.next()),
+                "--height" => self.arena_height = parse_value(&flag, args.next()),
+                "--topology" => self.topology = parse_value(&flag, args.next()), // "bounded" or "toroidal"
+                "--out-of-bounds" => self.out_of_bounds = parse_value(&flag, args.next()), // "wall", "empty" or "wrap"
+                "--senses" => self.sense_shape = parse_value(&flag, args.next()), // "moore" or "von-neumann"
+                "--sense-centre" => self.sense_centre = true,
+                "--record" => self.record_policy = parse_value(&flag, args.next()), // "never", "all", "improving" or every N generations
+                "--replay-dir" => self.replay_directory = parse_value(&flag, args.next()),
+                "--replay" => self.replay = Some(parse_value(&flag, args.next())),
+                "--compare" => self.compare = Some(parse_value(&flag, args.next())),
+                "--no-decisions" => self.record_decisions = false, // Lighter on memory for long runs, but nothing to inspect.
+                "--headless" => self.headless = true,
+                "--generations" => self.generations = Some(parse_value(&flag, args.next())),
+                "--export" => self.export = Some(parse_value(&flag, args.next())),
+                "--export-format" => self.export_format = parse_value(&flag, args.next()), // "gif" or "png"
+                "--export-to" => self.export_to = Some(parse_value(&flag, args.next())),
+                "--export-recorded" => self.export_recorded = true,
+                "--frame-delay" => self.frame_delay = parse_value(&flag, args.next()),
+                "--novelty-weight" => self.novelty_weight = parse_value(&flag, args.next()),
+                "--selection" => self.selection = parse_value(&flag, args.next()), // "roulette", "tournament", "truncation" or "pareto"
+                "--species" => self.species = parse_value(&flag, args.next()),
+                "--islands" => self.islands = parse_value(&flag, args.next()),
+                "--island" => self.island_args.push(parse_value(&flag, args.next())), // e.g. --island "--topology toroidal", once per island in order.
+                "--migrate-every" => self.migration_interval = parse_value(&flag, args.next()),
+                "--migrants" => self.migrants = parse_value(&flag, args.next()),
+                "--checkpoint" => self.checkpoint = parse_value(&flag, args.next()),
+                "--checkpoint-every" => self.checkpoint_interval = Some(parse_value(&flag, args.next())),
+                "--resume" => self.resume = Some(parse_value(&flag, args.next())),
+                "--hall-size" => self.hall_size = parse_value(&flag, args.next()),
+                "--reevaluate-every" => self.reevaluation_interval = parse_value(&flag, args.next()),
+                "--hunters" => self.hunters = parse_value(&flag, args.next()),
+                "--archived-rivals" => self.archived_rivals = parse_value(&flag, args.next()),
+                "--paintball" => self.paintball = true,
+                "--energy" => self.energy = parse_value(&flag, args.next()),
+                "--exhaustion" => self.exhaustion = parse_value(&flag, args.next()), // "freeze" or "die"
+                "--seed" => self.seed = Some(parse_value(&flag, args.next())),
+                "--script" => self.scripts.push(parse_value(&flag, args.next())), // e.g. "wall=random-walk", "wall=patrol:uurrddll", "beacon=flee" or "beacon=teleport:20"
+                _ => panic!("Unknown argument: {flag}"),
+            }
+        }
+    }
+    fn check(&self) {
+        assert!(!(self.headless && (self.replay.is_some() || self.compare.is_some())), "Watching a replay needs a window, try --export instead.");
+        assert!((0. ..=1.).contains(&self.novelty_weight), "The novelty weight goes from 0 to 1.");
+        assert!(self.arena_width >= 3 && self.arena_height >= 3, "The arena must be at least 3x3.");
+        let recipe = Map::new(0, 0).population;
+        let population = recipe.len();
+        assert!(room_for(self.arena_width, self.arena_height, self.topology) >= population, "A {}x{} arena is too small for the {population} creatures, it needs at least {population} tiles inside its edges.", self.arena_width, self.arena_height);
+        assert!(self.islands >= 1 && self.migration_interval >= 1, "There must be at least one island, and migrations at least every generation.");
+        assert!(self.checkpoint_interval != Some(0), "Checkpoints need to be at least one generation apart.");
+        assert!(self.resume.is_none() || self.replay.is_none(), "Resuming is for training, not watching a replay.");
+        let psychics = recipe.iter().filter(|s| **s == Species::Psychic).count();
+        assert!(self.hunters < psychics, "There are only {psychics} Psychics, some must be left as prey for the Hunters.");
+        assert!(!(self.paintball && self.hunters > 0), "Paintball and Hunters do not mix yet.");
+        assert!(self.energy >= 0., "Energy cannot be negative, use 0 to turn it off.");
+        assert!((0. ..1.).contains(&self.archived_rivals), "The share of archived rivals goes from 0 up to, but not including, 1.");
+    }
+}
+
+fn in_folder( // "checkpoints/latest.tgfc" becomes "checkpoints/island_0/latest.tgfc".
+    path: &Path,
+    folder: &str,
+) -> PathBuf {
+    let file = path.file_name().map(PathBuf::from).unwrap_or_default();
+    path.parent().unwrap_or(Path::new("")).join(folder).join(file)
+}
+
+fn parse_value<T: FromStr>(
+    flag: &str,
+    value: Option<String>,
+) -> T {
+    let Some(value) = value else {
+        panic!("Missing value after {flag}.")
+    };
+    match value.parse() {
+        Ok(parsed) => parsed,
+        Err(_) => panic!("Could not understand \"{value}\" given to {flag}."),
+    }
+}
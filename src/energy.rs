@@ -0,0 +1,89 @@
+use std::str::FromStr;
+
+use crate::{axiom::Axiom, map::{Map, Neighbourhood, Species}, simulation::get_adjacent_species};
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum Exhaustion { // What happens to a Psychic left without energy, for the rest of the generation.
+    #[default]
+    Freeze, // Stays where it is, in everyone's way.
+    Die, // Leaves the map.
+}
+
+impl FromStr for Exhaustion {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "freeze" => Ok(Exhaustion::Freeze),
+            "die" => Ok(Exhaustion::Die),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Energy { // Every Psychic starts each generation full, and has to come back to a Beacon to last.
+    pub capacity: f32,
+    pub move_cost: f32,
+    pub paint_cost: f32, // Also for tagging.
+    pub recharge: f32, // Per turn spent next to a Beacon.
+    pub exhaustion: Exhaustion,
+}
+
+impl Energy {
+    pub fn new(capacity: f32, exhaustion: Exhaustion) -> Self {
+        Self{capacity, move_cost: 1., paint_cost: 3., recharge: 5., exhaustion}
+    }
+    pub fn cost(&self, action: Axiom) -> f32 { // Standing still is free.
+        match action {
+            Axiom::Move { dx: 0, dy: 0 } => 0.,
+            Axiom::Move { .. } => self.move_cost,
+            Axiom::PaintAdjacent { .. } | Axiom::Tag => self.paint_cost,
+            _ => 0.,
+        }
+    }
+    pub fn spend( // What is left after this turn, recharge included.
+        &self,
+        energy: f32,
+        action: Axiom,
+        pos: (u32, u32),
+        map: &Map,
+    ) -> f32 {
+        let beacons = get_adjacent_species(pos, Neighbourhood::moore(1).with_out_of_bounds(map.out_of_bounds), map).into_iter().filter(|s| *s == Species::Beacon).count();
+        let recharge = if beacons > 0 { self.recharge } else { 0. };
+        (energy - self.cost(action) + recharge).min(self.capacity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moving_and_painting_cost_standing_still_does_not() {
+        let energy = Energy::new(10., Exhaustion::Freeze);
+        let map = Map::new(5, 5); // Nothing but empty tiles, no Beacon in reach.
+        assert_eq!(energy.spend(10., Axiom::Move{dx: 0, dy: 0}, (2, 2), &map), 10.);
+        assert_eq!(energy.spend(10., Axiom::Move{dx: 1, dy: 0}, (2, 2), &map), 9.);
+        assert_eq!(energy.spend(10., Axiom::PaintAdjacent{color: Species::TermiPainted}, (2, 2), &map), 7.);
+        assert_eq!(energy.spend(10., Axiom::Tag, (2, 2), &map), 7.);
+        assert_eq!(energy.spend(1., Axiom::Tag, (2, 2), &map), -2.);
+    }
+
+    #[test]
+    fn beacons_recharge_up_to_capacity() {
+        let energy = Energy::new(10., Exhaustion::Die);
+        let mut map = Map::new(5, 5);
+        let beacon = map.xy_idx(3, 3);
+        map.tiles[beacon] = Species::Beacon;
+        assert_eq!(energy.spend(2., Axiom::Move{dx: 1, dy: 0}, (2, 2), &map), 6.);
+        assert_eq!(energy.spend(9., Axiom::Move{dx: 0, dy: 0}, (2, 2), &map), 10.);
+        assert_eq!(energy.spend(2., Axiom::Move{dx: 0, dy: 0}, (1, 1), &map), 2.); // Two tiles away is too far.
+    }
+
+    #[test]
+    fn parses_exhaustion() {
+        assert_eq!("freeze".parse(), Ok(Exhaustion::Freeze));
+        assert_eq!("die".parse(), Ok(Exhaustion::Die));
+        assert_eq!("sleep".parse::<Exhaustion>(), Err(()));
+    }
+}
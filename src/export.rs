@@ -0,0 +1,117 @@
+use std::{fs, path::{Path, PathBuf}, str::FromStr};
+
+use image::{codecs::gif::{GifEncoder, Repeat}, imageops, Delay, Frame, GenericImageView, ImageResult, RgbaImage, Rgba};
+
+use crate::{map::Species, replay::{Replay, ReplayEntity}, theatre::get_texture_id};
+
+const SPRITE_SIZE: u32 = 16; // TILE_SIZE, but in pixels of the spritesheet.
+const SPRITESHEET_COLUMNS: u32 = 80;
+pub const DEFAULT_FRAME_DELAY: u32 = 200; // Milliseconds, same as the default theatre speed.
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum ExportFormat {
+    #[default]
+    Gif,
+    Png, // One numbered image per turn, in a folder.
+}
+
+impl FromStr for ExportFormat {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gif" => Ok(ExportFormat::Gif),
+            "png" => Ok(ExportFormat::Png),
+            _ => Err(()),
+        }
+    }
+}
+
+pub fn spritesheet_path() -> PathBuf { // Next to the executable when it was shipped with its assets, otherwise where it was built from.
+    let beside = std::env::current_exe().ok().and_then(|exe| Some(exe.parent()?.join("assets").join("spritesheet.png")));
+    match beside {
+        Some(path) if path.exists() => path,
+        _ => Path::new(env!("CARGO_MANIFEST_DIR")).join("assets").join("spritesheet.png"),
+    }
+}
+
+pub fn load_spritesheet() -> ImageResult<RgbaImage> {
+    Ok(image::open(spritesheet_path())?.to_rgba8())
+}
+
+pub fn export_replay( // Reads a saved replay and writes it out as pictures, no window needed. Returns where they went.
+    path: &Path,
+    format: ExportFormat,
+    output: Option<&Path>,
+    frame_delay: u32,
+) -> ImageResult<PathBuf> {
+    let replay = Replay::load(path)?;
+    let spritesheet = load_spritesheet()?;
+    let frames = render_frames(&replay, &spritesheet);
+    let output = match (output, format) {
+        (Some(output), _) => output.to_path_buf(),
+        (None, ExportFormat::Gif) => path.with_extension("gif"),
+        (None, ExportFormat::Png) => path.with_extension(""), // generation_000010.tgfp becomes the folder generation_000010
+    };
+    match format {
+        ExportFormat::Gif => write_gif(frames, &output, frame_delay)?,
+        ExportFormat::Png => write_pngs(&frames, &output)?,
+    }
+    Ok(output)
+}
+
+pub fn render_frames( // One picture per turn, drawn like the theatre would: black arena, creatures on top.
+    replay: &Replay,
+    spritesheet: &RgbaImage,
+) -> Vec<RgbaImage> {
+    let mut order: Vec<&ReplayEntity> = replay.entities.iter().collect();
+    order.sort_by_key(|e| e.identity.first().is_some_and(|s| s.is_psychic())); // Psychics last, so nothing hides them.
+    let mut frames = Vec::with_capacity(replay.turn_count());
+    for turn in 0..replay.turn_count() {
+        let mut frame = RgbaImage::from_pixel(replay.width * SPRITE_SIZE, replay.height * SPRITE_SIZE, Rgba([0, 0, 0, 255]));
+        for entity in order.iter() {
+            let (Some(&(x, y)), Some(&species)) = (entity.positions.get(turn), entity.identity.get(turn)) else { continue };
+            if x >= replay.width || y >= replay.height {
+                continue;
+            }
+            let sprite = sprite_of(spritesheet, species);
+            let top = (replay.height - 1 - y) * SPRITE_SIZE; // Images grow downwards, the arena grows upwards.
+            imageops::overlay(&mut frame, &sprite, (x * SPRITE_SIZE) as i64, top as i64);
+        }
+        frames.push(frame);
+    }
+    frames
+}
+
+fn sprite_of(
+    spritesheet: &RgbaImage,
+    species: Species,
+) -> RgbaImage {
+    let id = get_texture_id(species) as u32;
+    let (column, row) = (id % SPRITESHEET_COLUMNS, id / SPRITESHEET_COLUMNS);
+    spritesheet.view(column * SPRITE_SIZE, row * SPRITE_SIZE, SPRITE_SIZE, SPRITE_SIZE).to_image()
+}
+
+pub fn write_gif(
+    frames: Vec<RgbaImage>,
+    path: &Path,
+    frame_delay: u32,
+) -> ImageResult<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut encoder = GifEncoder::new(fs::File::create(path)?);
+    encoder.set_repeat(Repeat::Infinite)?;
+    let delay = Delay::from_numer_denom_ms(frame_delay, 1);
+    encoder.encode_frames(frames.into_iter().map(|f| Frame::from_parts(f, 0, 0, delay)))
+}
+
+pub fn write_pngs(
+    frames: &[RgbaImage],
+    directory: &Path,
+) -> ImageResult<()> {
+    fs::create_dir_all(directory)?;
+    for (turn, frame) in frames.iter().enumerate() {
+        frame.save(directory.join(format!("turn_{turn:04}.png")))?;
+    }
+    Ok(())
+}
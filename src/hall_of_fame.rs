@@ -0,0 +1,387 @@
+use bevy::{ecs::system::SystemParam, prelude::*, tasks::{AsyncComputeTaskPool, Task, block_on}};
+use rand::{Rng, SeedableRng, seq::IteratorRandom};
+use rand_chacha::ChaCha12Rng;
+
+use crate::{axiom::Axiom, compare::Compared, map::{Map, Species, build_map}, nn::Net, paintball::territory, scripts::Script, psychics::{FinishedTrace, Position, Soul, Trace}, replay::{Replay, ReplayEntity}, simulation::{Body, EvolutionSettings, GenerationFinished, Mind, TrainingRng, final_fitness, play_turn}, theatre::{TheatreSettings, replay_actors}, SpriteSheetHandle};
+
+pub struct HallOfFamePlugin; // Only the theatre side, the SimulationPlugin keeps the hall itself.
+
+impl Plugin for HallOfFamePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ChampionView{shown: None, description: String::new(), playing: None});
+        app.add_systems(Startup, draw_champion_label);
+        app.add_systems(Update, (show_champion, update_champion_label).chain());
+    }
+}
+
+const TRIALS: usize = 3; // Fresh maps when joining the hall, and at each re-evaluation.
+const LUCK_RATIO: f32 = 0.25; // Re-evaluated below this share of its rating, a champion is called out as lucky.
+
+#[derive(Clone)]
+pub struct Champion {
+    pub brain: Net,
+    pub action_choices: Vec<Axiom>,
+    pub species: Species, // Psychic, or the paintball faction it played for.
+    pub generation: usize, // The one it was the best of.
+    pub fitness: f32, // What it scored back then. Only shown: in training, the multipliers count the actions of a Psychic's whole life, not just of one brain.
+    pub trials: Vec<f32>, // Every run since, each on a map of its own.
+}
+
+impl Champion {
+    pub fn rating(&self) -> f32 { // The more trials, the less a single lucky run weighs.
+        self.trials.iter().sum::<f32>() / self.trials.len().max(1) as f32
+    }
+    pub fn play( // One trial per map, gives back their average.
+        &mut self,
+        template: &Map,
+        maps: &[Vec<Species>],
+        roster: &[Player],
+        settings: &EvolutionSettings,
+        rng: &mut impl Rng,
+    ) -> f32 {
+        let before = self.trials.len();
+        for tiles in maps.iter(){
+            let Some(trial) = run_trial(self, template, (template.width, template.height), tiles, roster, settings, rng) else { continue };
+            self.trials.push(trial.fitness);
+        }
+        let round = &self.trials[before..];
+        round.iter().sum::<f32>() / round.len().max(1) as f32
+    }
+}
+
+#[derive(Resource, Clone)]
+pub struct HallOfFame { // The best genomes of the whole run, not just of the last generation.
+    pub champions: Vec<Champion>, // Best rated first.
+    pub size: usize,
+    pub interval: usize, // Generations between two re-evaluations.
+}
+
+impl HallOfFame {
+    pub fn rank(&mut self) {
+        self.champions.sort_by(|a, b| b.rating().total_cmp(&a.rating()));
+        self.champions.truncate(self.size);
+    }
+    pub fn enter(&mut self, champion: Champion) { // Only if it does better than the weakest one already in.
+        let weakest = self.champions.last().map_or(f32::MIN, Champion::rating);
+        if self.champions.len() >= self.size && champion.rating() <= weakest {
+            return;
+        }
+        self.champions.push(champion);
+        self.rank();
+    }
+    pub fn judge(&mut self, verdict: Verdict) {
+        match verdict {
+            Verdict::Inducted(champion) => self.enter(champion),
+            Verdict::Reevaluated(generation, champions) => {
+                self.champions = champions;
+                self.rank();
+                info!("Generation {generation}: hall of fame re-evaluated.\n{}", self.summary());
+            },
+        }
+    }
+    pub fn settle(&mut self, trials: &mut PendingTrials) { // Waits for every trial still being played, before the hall gets saved or re-evaluated.
+        for task in trials.0.drain(..) {
+            self.judge(block_on(task));
+        }
+    }
+    pub fn summary(&self) -> String {
+        self.champions.iter().enumerate()
+            .map(|(i, c)| format!("{}. generation {}: rated {:.0} over {} trials, {:.0} in training", i + 1, c.generation, c.rating(), c.trials.len(), c.fitness))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+#[derive(Clone)]
+pub struct Player { // Someone from the population, sitting in for the rest of the game around a champion in its trials.
+    pub brain: Net,
+    pub action_choices: Vec<Axiom>,
+    pub species: Species,
+}
+
+pub fn roster<'a>( // The last generation as it was shipped, Hunters and both factions included.
+    psychics: impl Iterator<Item = (&'a Trace, &'a Soul)>,
+) -> Vec<Player> {
+    psychics
+        .filter_map(|(trace, soul)| Some(Player{brain: trace.shipped_brain.clone()?, action_choices: soul.action_choices.clone(), species: trace.original_species}))
+        .collect()
+}
+
+pub struct Trial { // One champion, in a whole generation of its own.
+    pub fitness: f32,
+    pub replay: Replay,
+}
+
+pub fn run_trial( // Plays a generation through the same play_turn as training, without the ECS. Every other seat goes to someone from the roster.
+    champion: &Champion,
+    template: &Map, // Only its rules are used: topology, edges, senses, energy and scripts.
+    (width, height): (u32, u32),
+    tiles: &[Species], // As on the first turn. The champion takes one of the tiles of its species.
+    roster: &[Player],
+    settings: &EvolutionSettings,
+    rng: &mut impl Rng,
+) -> Option<Trial> {
+    let start = tiles.iter().enumerate().filter(|(_, s)| **s == champion.species).map(|(i, _)| i).choose(rng)?;
+    let mut map = Map::new(width, height);
+    (map.topology, map.out_of_bounds) = (template.topology, template.out_of_bounds);
+    (map.sense_shape, map.sense_centre, map.rival_senses, map.teams) = (template.sense_shape, template.sense_centre, template.rival_senses, template.teams);
+    map.energy = template.energy;
+    map.tiles = tiles.to_vec();
+    let placed = |index: usize, species: Species| { // Standing on its tile, with its first turn filled like evolve_generation does.
+        let (x, y) = (index as u32 % width, index as u32 / width);
+        let mut trace = Trace::new(species);
+        trace.positions.push((x, y));
+        trace.identity.push(species);
+        (Position{x, y, starting_position: (x, y), benched: false}, trace, species)
+    };
+    let mut hylics: Vec<(Position, Trace, Species, Script)> = tiles.iter().enumerate()
+        .filter(|(_, s)| matches!(s, Species::Wall | Species::Beacon))
+        .map(|(i, s)| {
+            let (position, trace, species) = placed(i, *s);
+            (position, trace, species, template.script_for(*s))
+        })
+        .collect();
+    let mut psychics: Vec<(Position, Soul, Trace, Species)> = Vec::new();
+    let mut player = 0; // Where the champion sits among them.
+    for (i, species) in tiles.iter().enumerate().filter(|(_, s)| s.is_psychic()){
+        let (brain, action_choices) = match i == start {
+            true => {
+                player = psychics.len();
+                (&champion.brain, &champion.action_choices)
+            },
+            false => {
+                let team: Vec<&Player> = roster.iter().filter(|p| p.species == *species).collect();
+                let seated = psychics.iter().filter(|(_, _, _, s)| s == species).count();
+                let Some(other) = team.get(seated % team.len().max(1)) else {
+                    map.tiles[i] = Species::Nothing; // Nobody of that kind to play it.
+                    continue;
+                };
+                (&other.brain, &other.action_choices)
+            },
+        };
+        let (position, trace, species) = placed(i, *species);
+        psychics.push((position, Soul{nn: brain.clone(), action_choices: action_choices.clone(), ..default()}, trace, species));
+    }
+    {
+        let mut bodies: Vec<Body> = hylics.iter_mut().map(|(position, trace, species, script)| Body{position, trace, species, script}).collect();
+        let mut minds: Vec<Mind> = psychics.iter_mut().map(|(position, soul, trace, species)| Mind{position, soul, trace, species}).collect();
+        for turn in 0..settings.max_turn_number{
+            play_turn(&mut map, turn, &mut bodies, &mut minds, settings, true, rng);
+        }
+    }
+    let territory = territory(&map.tiles);
+    let scores: Vec<f32> = psychics.iter()
+        .map(|(position, soul, trace, _)| final_fitness(soul.fitness, (position.starting_position, (position.x, position.y)), &soul.actions_chosen, trace.original_species, &territory, &map, &settings.fitness))
+        .collect();
+    let fitness = scores[player];
+    let entities = hylics.into_iter()
+        .map(|(_, trace, _, _)| ReplayEntity{fitness: 0., positions: trace.positions, identity: trace.identity, actions: trace.actions, action_choices: Vec::new(), records: Vec::new()})
+        .chain(psychics.into_iter().zip(scores).map(|((_, soul, trace, _), fitness)| ReplayEntity{fitness, positions: trace.positions, identity: trace.identity, actions: trace.actions, action_choices: soul.action_choices, records: trace.records}))
+        .collect();
+    Some(Trial{fitness, replay: Replay{generation: 0, width, height, tiles: tiles.to_vec(), entities}})
+}
+
+pub enum Verdict { // What some trials, once over, do to the hall.
+    Inducted(Champion), // A candidate, in if it did well enough.
+    Reevaluated(usize, Vec<Champion>), // The whole hall with a round more of trials, and the generation it was sent off at.
+}
+
+#[derive(Resource, Default)]
+pub struct PendingTrials(pub Vec<Task<Verdict>>); // Played in the background so the frame does not wait on them. Judged in the order they were sent off.
+
+pub fn induct_champion( // The best of every generation gets a few trials, see welcome_champions for the rest.
+    mut finished: EventReader<GenerationFinished>,
+    hall: Res<HallOfFame>,
+    mut trials: ResMut<PendingTrials>,
+    psychics: Query<(&Trace, &Soul)>,
+    map: Res<Map>,
+    settings: Res<EvolutionSettings>,
+    mut rng: ResMut<TrainingRng>,
+){
+    let Some(event) = finished.read().last() else { return };
+    if event.generation == 0 || hall.size == 0 { // Generation 0 is the empty one evolved on startup.
+        return;
+    }
+    let Some((trace, soul)) = psychics.iter()
+        .filter(|(trace, _)| trace.shipped_brain.is_some())
+        .max_by(|a, b| a.0.shipped_fitness.total_cmp(&b.0.shipped_fitness)) else { return };
+    let mut champion = Champion{
+        brain: trace.shipped_brain.clone().unwrap(),
+        action_choices: soul.action_choices.clone(),
+        species: trace.original_species,
+        generation: event.generation,
+        fitness: trace.shipped_fitness,
+        trials: Vec::new(),
+    };
+    let roster = roster(psychics.iter());
+    let (template, settings) = (map.clone(), settings.clone()); // Taken now, the next generation may change them before the trials are over.
+    let mut dice = ChaCha12Rng::seed_from_u64(rng.0.gen()); // Its own, so however long the trials take, training draws the same numbers.
+    trials.0.push(AsyncComputeTaskPool::get().spawn(async move {
+        champion.play(&template, &fresh_maps(&template, &mut dice), &roster, &settings, &mut dice);
+        Verdict::Inducted(champion)
+    }));
+}
+
+pub fn welcome_champions( // Whoever is done with its trials gets in, if it did better there than the weakest one already in. Re-evaluations take effect here too.
+    mut hall: ResMut<HallOfFame>,
+    mut trials: ResMut<PendingTrials>,
+){
+    while trials.0.first().is_some_and(Task::is_finished) { // In the order they were sent off, like settle does.
+        let task = trials.0.remove(0);
+        hall.judge(block_on(task)); // Already finished, nothing to wait for.
+    }
+}
+
+pub fn reevaluate_champions( // Every few generations, each champion plays again on fresh maps. Whoever only got lucky once sinks.
+    mut finished: EventReader<GenerationFinished>,
+    mut hall: ResMut<HallOfFame>,
+    mut trials: ResMut<PendingTrials>,
+    psychics: Query<(&Trace, &Soul)>,
+    map: Res<Map>,
+    settings: Res<EvolutionSettings>,
+    mut rng: ResMut<TrainingRng>,
+){
+    let Some(event) = finished.read().last() else { return };
+    if event.generation == 0 || hall.interval == 0 || event.generation % hall.interval != 0 {
+        return;
+    }
+    hall.settle(&mut trials); // Everyone inducted so far plays this round.
+    if hall.champions.is_empty() {
+        return;
+    }
+    let mut champions = hall.champions.clone();
+    let roster = roster(psychics.iter());
+    let (template, settings, generation) = (map.clone(), settings.clone(), event.generation);
+    let mut dice = ChaCha12Rng::seed_from_u64(rng.0.gen());
+    trials.0.push(AsyncComputeTaskPool::get().spawn(async move {
+        let maps = fresh_maps(&template, &mut dice); // The same ones for everybody, so they are compared fairly.
+        for champion in champions.iter_mut(){
+            let (rating, trials) = (champion.rating(), champion.trials.len());
+            let round = champion.play(&template, &maps, &roster, &settings, &mut dice);
+            if round < rating * LUCK_RATIO {
+                info!("The champion of generation {} looks lucky: rated {rating:.0} over {trials} trials, {round:.0} on average now.", champion.generation);
+            }
+        }
+        Verdict::Reevaluated(generation, champions)
+    }));
+}
+
+fn fresh_maps(
+    map: &Map,
+    rng: &mut impl Rng,
+) -> Vec<Vec<Species>> {
+    (0..TRIALS).map(|_| build_map(map.population.clone(), map.width, map.height, map.topology, rng).0).collect()
+}
+
+#[derive(Resource)]
+pub struct ChampionView {
+    pub shown: Option<usize>, // Index in the hall.
+    pub description: String,
+    pub playing: Option<(usize, String, Task<Option<Trial>>)>, // The next one to show, still in its trial, and what to say about it.
+}
+
+#[derive(Component)]
+pub struct ChampionLabel;
+
+#[derive(SystemParam)]
+pub struct Stage<'w, 's> { // What it takes to put a replay in the theatre, like open_replay does.
+    commands: Commands<'w, 's>,
+    actors: Query<'w, 's, Entity, (With<FinishedTrace>, Without<Compared>)>,
+    tex_handle: Res<'w, SpriteSheetHandle>,
+    theatre: ResMut<'w, TheatreSettings>,
+}
+
+impl Stage<'_, '_> {
+    fn play(&mut self, replay: &Replay) {
+        for actor in self.actors.iter(){
+            self.commands.entity(actor).despawn();
+        }
+        for actor in replay_actors(replay, &self.tex_handle){
+            self.commands.spawn(actor);
+        }
+        self.theatre.requested_turn = Some(0);
+        self.theatre.max_turn_number = replay.turn_count();
+    }
+}
+
+fn draw_champion_label(
+    mut commands: Commands,
+){
+    commands.spawn((TextBundle::from_section(
+        "",
+        TextStyle {
+            font_size: 16.,
+            color: Color::WHITE,
+            ..default()
+        },
+    ).with_style(Style {
+        position_type: PositionType::Absolute,
+        bottom: Val::Px(50.),
+        left: Val::Px(4.),
+        ..default()
+    }), ChampionLabel));
+}
+
+fn show_champion( // C plays the next champion on the map of the shipped generation, Shift+C on a brand new one. Space goes back to the generation.
+    keys: Res<Input<KeyCode>>,
+    hall: Option<Res<HallOfFame>>,
+    mut view: ResMut<ChampionView>,
+    map: Res<Map>,
+    settings: Res<EvolutionSettings>,
+    psychics: Query<(&Trace, &Soul)>,
+    mut stage: Stage,
+){
+    if keys.just_pressed(KeyCode::Space) && (view.shown.is_some() || view.playing.is_some()) {
+        *view = ChampionView{shown: None, description: String::new(), playing: None}; // Dropping its task cancels the trial.
+    }
+    if view.playing.as_ref().is_some_and(|(_, _, task)| task.is_finished()) {
+        let (index, description, task) = view.playing.take().unwrap();
+        match block_on(task) { // Already finished, nothing to wait for.
+            Some(trial) => {
+                stage.play(&trial.replay);
+                view.shown = Some(index);
+                view.description = format!("{description}{:.0}", trial.fitness);
+            },
+            None => warn!("No room for that champion on that map."),
+        }
+    }
+    if !keys.just_pressed(KeyCode::C) {
+        return;
+    }
+    let Some(hall) = hall.filter(|h| !h.champions.is_empty()) else {
+        info!("The hall of fame is still empty.");
+        return;
+    };
+    let after = view.playing.as_ref().map(|(i, _, _)| *i).or(view.shown);
+    let index = after.map_or(0, |i| (i + 1) % hall.champions.len());
+    let champion = hall.champions[index].clone();
+    let mut rng = ChaCha12Rng::from_entropy(); // Not the training dice, watching must not change how training goes.
+    let fresh = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) || map.shipped_tiles.is_empty();
+    let (size, tiles) = match fresh {
+        true => ((map.width, map.height), build_map(map.population.clone(), map.width, map.height, map.topology, &mut rng).0),
+        false => ((map.shipped_width, map.shipped_height), map.shipped_tiles.clone()),
+    };
+    let description = format!(
+        "Champion {}/{}: generation {}, rated {:.0} over {} trials. Here, on {} map: ",
+        index + 1, hall.champions.len(), champion.generation, champion.rating(), champion.trials.len(),
+        if fresh { "a new" } else { "the last" },
+    );
+    let (template, settings, roster) = (map.clone(), settings.clone(), roster(psychics.iter()));
+    view.playing = Some((index, description, AsyncComputeTaskPool::get().spawn(async move { // Pressing C again before it is shown skips to the next one.
+        let mut trial = run_trial(&champion, &template, size, &tiles, &roster, &settings, &mut rng)?;
+        trial.replay.generation = champion.generation;
+        Some(trial)
+    })));
+}
+
+fn update_champion_label(
+    view: Res<ChampionView>,
+    mut label: Query<&mut Text, With<ChampionLabel>>,
+){
+    if !view.is_changed() {
+        return;
+    }
+    for mut text in label.iter_mut(){
+        text.sections[0].value = view.description.clone();
+    }
+}
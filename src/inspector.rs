@@ -0,0 +1,173 @@
+use bevy::{prelude::*, window::PrimaryWindow};
+
+use crate::{axiom::Axiom, compare::Comparison, map::Map, psychics::FinishedTrace, theatre::{TILE_SIZE, TheatreSettings}, ui::CameraDrag};
+
+pub struct InspectorPlugin;
+
+impl Plugin for InspectorPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Inspected{actor: None});
+        app.add_systems(Startup, draw_inspector_panel);
+        app.add_systems(Update, (stop_inspecting, select_actor, update_inspector_panel, draw_brain).chain());
+    }
+}
+
+const LAYER_SPACING: f32 = 160.;
+
+#[derive(Resource)]
+pub struct Inspected { // The theatre creature whose brain is being looked at, if any.
+    pub actor: Option<Entity>,
+}
+
+#[derive(Component)]
+pub struct InspectorPanel;
+
+fn draw_inspector_panel(
+    mut commands: Commands,
+){
+    commands.spawn((TextBundle::from_section(
+        "",
+        TextStyle {
+            font_size: 14.,
+            color: Color::WHITE,
+            ..default()
+        },
+    ).with_style(Style {
+        position_type: PositionType::Absolute,
+        top: Val::Px(4.),
+        right: Val::Px(4.),
+        padding: UiRect::all(Val::Px(6.)),
+        ..default()
+    }).with_background_color(Color::rgba(0., 0., 0., 0.8)), InspectorPanel));
+}
+
+fn stop_inspecting( // Escape.
+    keys: Res<Input<KeyCode>>,
+    mut inspected: ResMut<Inspected>,
+){
+    if keys.just_pressed(KeyCode::Escape) {
+        inspected.actor = None;
+    }
+}
+
+fn select_actor( // Left click on a creature to inspect it, click on nothing to stop. Dragging pans instead.
+    mouse: Res<Input<MouseButton>>,
+    drag: Res<CameraDrag>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    camera: Query<(&Camera, &GlobalTransform), With<Camera2d>>,
+    actors: Query<(Entity, &Transform, &FinishedTrace)>,
+    ui: Query<&Interaction>, // The timeline, the settings panel and their buttons.
+    mut inspected: ResMut<Inspected>,
+){
+    if !mouse.just_released(MouseButton::Left) || drag.dragged || ui.iter().any(|i| *i != Interaction::None) {
+        return;
+    }
+    let Some(cursor) = window.get_single().ok().and_then(|w| w.cursor_position()) else { return };
+    let Ok((camera, camera_transform)) = camera.get_single() else { return };
+    let Some(point) = camera.viewport_to_world_2d(camera_transform, cursor) else { return };
+    inspected.actor = actors.iter().find(|(_, transform, trace)| {
+        let corner = transform.translation.truncate(); // Sprites are anchored on their bottom left.
+        let inside = point.x >= corner.x && point.x < corner.x + TILE_SIZE && point.y >= corner.y && point.y < corner.y + TILE_SIZE;
+        inside && (trace.brain.is_some() || !trace.records.is_empty())
+    }).map(|(entity, _, _)| entity);
+}
+
+fn update_inspector_panel(
+    inspected: Res<Inspected>,
+    config: Res<TheatreSettings>,
+    actors: Query<&FinishedTrace>,
+    mut panel: Query<(&mut Text, &mut Visibility), With<InspectorPanel>>,
+){
+    let Ok((mut text, mut visibility)) = panel.get_single_mut() else { return };
+    let Some(trace) = inspected.actor.and_then(|a| actors.get(a).ok()) else {
+        *visibility = Visibility::Hidden;
+        return;
+    };
+    *visibility = Visibility::Visible;
+    let turn = config.current_turn;
+    let mut lines = Vec::new();
+    match trace.source {
+        Some(source) => lines.push(format!("Psychic {source:?}")),
+        None => lines.push("Psychic".to_owned()),
+    }
+    lines.push(format!("Fitness: {:.1}", trace.fitness));
+    let mut tally: Vec<(Axiom, usize)> = Vec::new();
+    for action in trace.actions.iter(){
+        match tally.iter_mut().find(|(a, _)| a == action) {
+            Some((_, count)) => *count += 1,
+            None => tally.push((*action, 1)),
+        }
+    }
+    let tally: Vec<String> = tally.iter().map(|(a, count)| format!("{} x{count}", describe_axiom(*a))).collect();
+    lines.push(format!("Actions chosen: {}", tally.join(", ")));
+    lines.push(format!("--- Turn {} ---", turn + 1));
+    match trace.records.get(turn) {
+        Some(record) => {
+            lines.push("Senses:".to_owned());
+            for row in record.senses.chunks(8){
+                let row: Vec<String> = row.iter().map(|v| format!("{v:.1}")).collect();
+                lines.push(format!("  {}", row.join(" ")));
+            }
+            lines.push("Outputs:".to_owned());
+            for (i, value) in record.outputs.iter().enumerate(){
+                let label = trace.action_choices.get(i).map(|a| describe_axiom(*a)).unwrap_or_else(|| format!("#{i}"));
+                let marker = if trace.action_choices.get(i) == Some(&record.action) { " <" } else { "" };
+                lines.push(format!("  {label}: {value:.3}{marker}"));
+            }
+            lines.push(format!("Chose {}, fitness {:+.1}", describe_axiom(record.action), record.fitness_delta));
+        },
+        None => lines.push("Senses and outputs were not recorded.".to_owned()),
+    }
+    text.sections[0].value = lines.join("\n");
+}
+
+fn draw_brain( // Node-link drawing of the inspected brain, to the right of the arena. Blue is positive, red is negative.
+    inspected: Res<Inspected>,
+    config: Res<TheatreSettings>,
+    actors: Query<&FinishedTrace>,
+    map: Res<Map>,
+    comparison: Option<Res<Comparison>>,
+    mut gizmos: Gizmos,
+){
+    let Some(trace) = inspected.actor.and_then(|a| actors.get(a).ok()) else { return };
+    let Some(brain) = &trace.brain else { return };
+    let activations = trace.records.get(config.current_turn).map(|r| brain.activations(&r.senses));
+    let weights = brain.weights();
+    let mut sizes = vec![weights[0][0].len() - 1]; // The first weight of each node is its bias.
+    sizes.extend(weights.iter().map(|l| l.len()));
+    let beside = comparison.map_or(0., |c| c.extra_width(&map)); // Stay clear of a compared arena.
+    let origin = Vec2::new((map.width as f32 + 4.) * TILE_SIZE + beside, 0.);
+    let height = map.height as f32 * TILE_SIZE;
+    let node_position = |layer: usize, node: usize| -> Vec2 {
+        origin + Vec2::new(layer as f32 * LAYER_SPACING, height * (node as f32 + 0.5) / sizes[layer] as f32)
+    };
+    for (l, layer) in weights.iter().enumerate(){
+        for (n, node) in layer.iter().enumerate(){
+            for (p, weight) in node.iter().skip(1).enumerate(){
+                let alpha = (weight.abs() as f32 / 2.).min(1.) * 0.6;
+                let color = if *weight >= 0. { Color::rgba(0.3, 0.6, 1., alpha) } else { Color::rgba(1., 0.3, 0.3, alpha) };
+                gizmos.line_2d(node_position(l, p), node_position(l + 1, n), color);
+            }
+        }
+    }
+    for (l, size) in sizes.iter().enumerate(){
+        for n in 0..*size{
+            let value = activations.as_ref().and_then(|a| a.get(l)).and_then(|a| a.get(n)).copied().unwrap_or(0.) as f32;
+            let shade = 0.25 + 0.75 * value; // Still visible on the black background when inactive.
+            gizmos.circle_2d(node_position(l, n), 3., Color::rgb(shade, shade, shade));
+        }
+    }
+}
+
+pub fn describe_axiom(
+    axiom: Axiom
+) -> String {
+    match axiom {
+        Axiom::Move { dx, dy } => format!("Move ({dx}, {dy})"),
+        Axiom::PaintAdjacent { color } => format!("Paint {color:?}"),
+        Axiom::SpeciesTransform { new_species } => format!("Become {new_species:?}"),
+        Axiom::Void => "Nothing".to_owned(),
+        Axiom::Tag => "Tag".to_owned(),
+        Axiom::Teleport { x, y } => format!("Teleport to ({x}, {y})"),
+    }
+}
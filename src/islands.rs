@@ -0,0 +1,122 @@
+use std::{sync::{Arc, Mutex}, thread};
+
+use bevy::prelude::*;
+use tracing_subscriber::EnvFilter;
+
+use crate::{checkpoint::CheckpointPlugin, coevolution::{CoevolutionPlugin, field_rivals}, map::Species, config::Config, map::MapPlugin, nn::Net, psychics::{PsychicPlugin, Soul, Trace}, replay::ReplayPlugin, simulation::{GenerationFinished, SimulationPlugin, evolve_generation}};
+
+pub struct IslandPlugin;
+
+impl Plugin for IslandPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, migrate.after(evolve_generation).after(field_rivals)); // Migrants land on whatever the Hunters' stand-ins left.
+    }
+}
+
+pub struct Migrant {
+    pub brain: Net,
+    pub fitness: f32,
+    pub from: usize,
+    pub species: Species, // Prey only replace prey, Hunters only Hunters.
+}
+
+pub struct Harbour { // Shared by every island. Each has a mailbox, and the next one in the ring drops its migrants there.
+    mailboxes: Vec<Mutex<Vec<Migrant>>>,
+    best_fitness: Vec<Mutex<f32>>,
+}
+
+#[derive(Resource)]
+pub struct Island {
+    pub index: usize,
+    pub count: usize,
+    pub harbour: Arc<Harbour>,
+    pub interval: usize,
+    pub migrants: usize,
+}
+
+pub fn run_islands( // One headless training per island, each on its own thread, until all of them are done.
+    config: &Config,
+){
+    let harbour = Arc::new(Harbour{
+        mailboxes: (0..config.islands).map(|_| Mutex::new(Vec::new())).collect(),
+        best_fitness: (0..config.islands).map(|_| Mutex::new(0.)).collect(),
+    });
+    tracing_subscriber::fmt() // Logging is global, set it up before any island logs or enters its span. Same defaults as Bevy's LogPlugin.
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info,wgpu=error,naga=warn")))
+        .init();
+    let handles: Vec<_> = (0..config.islands).map(|index| {
+        let island_config = config.for_island(index);
+        let island = Island{index, count: config.islands, harbour: harbour.clone(), interval: config.migration_interval, migrants: config.migrants};
+        thread::Builder::new()
+            .name(format!("island {index}"))
+            .spawn(move || {
+                let _span = info_span!("island", index).entered(); // So the logs say which island they come from.
+                App::new()
+                    .add_plugins(MinimalPlugins)
+                    .insert_resource(island_config)
+                    .insert_resource(island)
+                    .add_plugins(MapPlugin)
+                    .add_plugins(PsychicPlugin)
+                    .add_plugins(SimulationPlugin)
+                    .add_plugins(ReplayPlugin)
+                    .add_plugins(CheckpointPlugin)
+                    .add_plugins(CoevolutionPlugin)
+                    .add_plugins(IslandPlugin)
+                    .run();
+            })
+            .expect("Could not start an island")
+    }).collect();
+    for (index, handle) in handles.into_iter().enumerate(){
+        if handle.join().is_err() {
+            error!("Island {index} crashed.");
+        }
+    }
+    for (index, best) in harbour.best_fitness.iter().enumerate(){
+        info!("Island {index}: best fitness {:.0}.", *best.lock().unwrap());
+    }
+}
+
+fn migrate( // Every few generations, the fittest leave for the next island, and whoever arrived replaces the least fit.
+    mut finished: EventReader<GenerationFinished>,
+    island: Res<Island>,
+    mut psychics: Query<(&mut Soul, &Trace)>,
+){
+    let Some(event) = finished.read().last() else { return };
+    {
+        let mut best = island.harbour.best_fitness[island.index].lock().unwrap();
+        *best = best.max(event.best_fitness);
+    }
+    if island.count < 2 || event.generation == 0 || event.generation % island.interval != 0 {
+        return;
+    }
+    let mut ranked: Vec<(f32, Net, Species)> = psychics.iter() // The Souls already hold the offspring, the parents were shipped.
+        .filter_map(|(_, trace)| Some((trace.shipped_fitness, trace.shipped_brain.clone()?, trace.original_species)))
+        .collect();
+    ranked.sort_by(|a, b| b.0.total_cmp(&a.0));
+    let leaving: Vec<Migrant> = ranked.into_iter().take(island.migrants)
+        .map(|(fitness, brain, species)| Migrant{brain, fitness, from: island.index, species})
+        .collect();
+    let departed = leaving.len();
+    let destination = (island.index + 1) % island.count;
+    island.harbour.mailboxes[destination].lock().unwrap().extend(leaving);
+    let arrived = std::mem::take(&mut *island.harbour.mailboxes[island.index].lock().unwrap());
+    if arrived.is_empty() {
+        info!("Generation {}: {departed} left for island {destination}.", event.generation);
+        return;
+    }
+    let mut slots: Vec<(f32, Mut<Soul>, Species)> = psychics.iter_mut().map(|(soul, trace)| (trace.shipped_fitness, soul, trace.original_species)).collect();
+    slots.sort_by(|a, b| a.0.total_cmp(&b.0)); // Least fit parents first.
+    let mut taken = vec![false; slots.len()];
+    let mut settled = 0;
+    for migrant in arrived {
+        let Some(slot) = (0..slots.len()).find(|i| !taken[*i] && slots[*i].2 == migrant.species && slots[*i].1.nn.shape() == migrant.brain.shape()) else {
+            warn!("A migrant from island {} (fitness {:.0}) found no room for a brain of its shape.", migrant.from, migrant.fitness);
+            continue;
+        };
+        slots[slot].1.nn = migrant.brain;
+        slots[slot].1.stand_in = false; // A brain of this generation again, free to breed.
+        taken[slot] = true;
+        settled += 1;
+    }
+    info!("Generation {}: {departed} left for island {destination}, {settled} arrived.", event.generation);
+}
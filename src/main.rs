@@ -6,11 +6,39 @@ mod util;
 mod map;
 mod axiom;
 mod simulation;
+mod config;
+mod replay;
+mod inspector;
+mod overlay;
+mod export;
+mod compare;
+mod panel;
+mod novelty;
+mod pareto;
+mod speciation;
+mod islands;
+mod checkpoint;
+mod hall_of_fame;
+mod coevolution;
+mod paintball;
+mod scripts;
+mod energy;
 
 use bevy::prelude::*;
 use bevy_tweening::TweeningPlugin;
+use config::Config;
 use map::MapPlugin;
 use psychics::PsychicPlugin;
+use replay::ReplayPlugin;
+use inspector::InspectorPlugin;
+use overlay::OverlayPlugin;
+use compare::ComparePlugin;
+use checkpoint::CheckpointPlugin;
+use coevolution::CoevolutionPlugin;
+use hall_of_fame::HallOfFamePlugin;
+use panel::PanelPlugin;
+use pareto::ParetoPlugin;
+use speciation::SpeciationPlugin;
 use simulation::SimulationPlugin;
 use ui::UIPlugin;
 //use bevy_inspector_egui::quick::WorldInspectorPlugin;
@@ -18,34 +46,72 @@ use theatre::TheatrePlugin;
 //use bevy::input::common_conditions::input_toggle_active;
 
 fn main() {
-    App::new()
-        .add_plugins(
-            DefaultPlugins
-                .set(ImagePlugin::default_nearest())
-                .set(WindowPlugin {
-                    primary_window: Some(Window {
-                        title: "TGFP".into(),
-                        resolution: (1024.0, 576.0).into(),
-                        //resizable: false,
+    let config = Config::from_args();
+    if let Some(path) = &config.export { // Only turning a replay into pictures, no need for Bevy at all.
+        match export::export_replay(path, config.export_format, config.export_to.as_deref(), config.frame_delay) {
+            Ok(output) => println!("Exported {} to {}.", path.display(), output.display()),
+            Err(e) => {
+                eprintln!("Could not export {}: {e}", path.display());
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if config.islands > 1 { // Several populations, each with its own App on its own thread.
+        islands::run_islands(&config);
+        return;
+    }
+    let mut app = App::new();
+    if config.headless { // No window, no GPU, no theatre. Just training as fast as it goes.
+        app.add_plugins((MinimalPlugins, bevy::log::LogPlugin::default())); // The logs are all there is to see.
+    } else {
+        app
+            .add_plugins(
+                DefaultPlugins
+                    .set(ImagePlugin::default_nearest())
+                    .set(WindowPlugin {
+                        primary_window: Some(Window {
+                            title: "TGFP".into(),
+                            resolution: (1024.0, 576.0).into(),
+                            //resizable: false,
+                            ..default()
+                        }),
                         ..default()
-                    }),
-                    ..default()
-                })
-                .build(),
-        )
-        .insert_resource(ClearColor(Color::rgb(0., 0., 0.)))
-        .add_systems(PreStartup, load_spritesheet)
-        .add_systems(Startup, load_camera)
-        .add_plugins(TweeningPlugin)
-        .add_plugins(MapPlugin)
-        .add_plugins(UIPlugin)
-        .add_plugins(PsychicPlugin)
-        .add_plugins(SimulationPlugin)
-        .add_plugins(TheatrePlugin)
-        //.add_plugins(
-        //    WorldInspectorPlugin::default().run_if(input_toggle_active(true, KeyCode::Escape)),
-        //)
-        .run();
+                    })
+                    .build(),
+            )
+            .insert_resource(ClearColor(Color::rgb(0., 0., 0.)))
+            .add_systems(PreStartup, load_spritesheet)
+            .add_systems(Startup, load_camera)
+            .add_plugins(TweeningPlugin);
+    }
+    app
+        .insert_resource(config.clone())
+        .add_plugins(MapPlugin);
+    if config.replay.is_none() { // Watching a saved replay does not need any training going on.
+        app
+            .add_plugins(PsychicPlugin)
+            .add_plugins(SimulationPlugin)
+            .add_plugins(ReplayPlugin)
+            .add_plugins(CheckpointPlugin)
+            .add_plugins(CoevolutionPlugin);
+    }
+    if !config.headless {
+        app
+            .add_plugins(UIPlugin)
+            .add_plugins(TheatrePlugin)
+            .add_plugins(InspectorPlugin)
+            .add_plugins(OverlayPlugin)
+            .add_plugins(ComparePlugin)
+            .add_plugins(ParetoPlugin);
+            //.add_plugins(
+            //    WorldInspectorPlugin::default().run_if(input_toggle_active(true, KeyCode::Escape)),
+            //)
+        if config.replay.is_none() { // Nothing to tweak when only watching.
+            app.add_plugins((PanelPlugin, SpeciationPlugin, HallOfFamePlugin));
+        }
+    }
+    app.run();
 }
 
 fn load_spritesheet( // I am so glad this works. Just looking at this code is going to make me fail NNN. - 8th November 2023
@@ -1,13 +1,126 @@
-use rand::{Rng, seq::IteratorRandom, thread_rng};
+use std::str::FromStr;
+
+use rand::{Rng, seq::IteratorRandom};
 use bevy::prelude::*;
 
-use crate::{simulation::{PLAY_AREA_WIDTH, PLAY_AREA_HEIGHT}, axiom::Axiom};
+use crate::{axiom::Axiom, config::Config, energy::Energy, scripts::{Script, ScriptRule}};
 
 pub struct MapPlugin;
 
 impl Plugin for MapPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(Map::new());
+        let config = app.world.get_resource::<Config>().cloned().unwrap_or_default();
+        let mut map = Map::new(config.arena_width, config.arena_height);
+        map.add_hunters(config.hunters);
+        if config.paintball {
+            map.split_teams();
+        }
+        map.scripts = config.scripts.clone();
+        map.energy = (config.energy > 0.).then(|| Energy::new(config.energy, config.exhaustion));
+        map.topology = config.topology;
+        map.out_of_bounds = config.out_of_bounds;
+        map.sense_shape = config.sense_shape;
+        map.sense_centre = config.sense_centre;
+        app.insert_resource(map);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum Topology {
+    #[default]
+    Bounded, // The edges of the arena are walls.
+    Toroidal, // Walking off an edge brings you back on the opposite one, like Pac-Man.
+}
+
+impl FromStr for Topology {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bounded" => Ok(Topology::Bounded),
+            "toroidal" => Ok(Topology::Toroidal),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum NeighbourhoodShape {
+    #[default]
+    Moore, // The full square around the centre.
+    VonNeumann, // Only tiles within walking distance, a diamond.
+}
+
+impl FromStr for NeighbourhoodShape {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "moore" => Ok(NeighbourhoodShape::Moore),
+            "von-neumann" => Ok(NeighbourhoodShape::VonNeumann),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum OutOfBounds { // What is found past the edge of a bounded arena.
+    #[default]
+    Wall,
+    Empty,
+    Wrap,
+}
+
+impl FromStr for OutOfBounds {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wall" => Ok(OutOfBounds::Wall),
+            "empty" => Ok(OutOfBounds::Empty),
+            "wrap" => Ok(OutOfBounds::Wrap),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Neighbourhood {
+    pub shape: NeighbourhoodShape,
+    pub range: i32,
+    pub include_centre: bool,
+    pub out_of_bounds: OutOfBounds,
+}
+
+impl Neighbourhood {
+    pub const fn moore(range: i32) -> Self {
+        Self { shape: NeighbourhoodShape::Moore, range, include_centre: false, out_of_bounds: OutOfBounds::Wall }
+    }
+    pub const fn von_neumann(range: i32) -> Self {
+        Self { shape: NeighbourhoodShape::VonNeumann, range, include_centre: false, out_of_bounds: OutOfBounds::Wall }
+    }
+    pub const fn with_centre(mut self) -> Self {
+        self.include_centre = true;
+        self
+    }
+    pub const fn with_out_of_bounds(mut self, out_of_bounds: OutOfBounds) -> Self {
+        self.out_of_bounds = out_of_bounds;
+        self
+    }
+    pub fn offsets(&self) -> Vec<(i32, i32)> { // Always in the same order, the neural networks depend on it.
+        let mut output = Vec::new();
+        for dx in -self.range..=self.range{
+            for dy in -self.range..=self.range{
+                let inside = match self.shape {
+                    NeighbourhoodShape::Moore => true,
+                    NeighbourhoodShape::VonNeumann => dx.abs() + dy.abs() <= self.range,
+                };
+                if inside && (self.include_centre || (dx, dy) != (0, 0)) {
+                    output.push((dx, dy));
+                }
+            }
+        }
+        output
+    }
+    pub fn size(&self) -> usize {
+        self.offsets().len()
     }
 }
 
@@ -18,38 +131,84 @@ pub enum Species {
     Psychic,
     Beacon,
     TermiPainted,
+    Hunter, // A Psychic of the other population, evolved to catch the first one.
+    Caught, // A Psychic who got tagged. It stays put until the generation ends.
+    Red, // The two paintball factions.
+    Blue,
+    RedPainted, // A wall in their colours.
+    BluePainted,
+}
+
+impl Species {
+    pub fn is_psychic(self) -> bool { // Has a brain.
+        matches!(self, Species::Psychic | Species::Hunter | Species::Red | Species::Blue)
+    }
+    pub fn is_paint(self) -> bool {
+        matches!(self, Species::TermiPainted | Species::RedPainted | Species::BluePainted)
+    }
+    pub fn paint(self) -> Species { // The colour it paints walls in.
+        match self {
+            Species::Red => Species::RedPainted,
+            Species::Blue => Species::BluePainted,
+            _ => Species::TermiPainted,
+        }
+    }
+    pub fn opponent(self) -> Option<Species> { // The other paintball faction.
+        match self {
+            Species::Red => Some(Species::Blue),
+            Species::Blue => Some(Species::Red),
+            _ => None,
+        }
+    }
+}
+
+pub fn room_for( // How many creatures fit on an arena, at most: everything but the edges of a bounded one.
+    width: u32,
+    height: u32,
+    topology: Topology,
+) -> usize {
+    match topology {
+        Topology::Bounded => (width.saturating_sub(2) * height.saturating_sub(2)) as usize,
+        Topology::Toroidal => (width * height) as usize,
+    }
 }
 
 pub fn build_map(
     parameters: Vec<Species>,
+    width: u32,
+    height: u32,
+    topology: Topology,
+    rng: &mut impl Rng,
 ) -> (Vec<Species>, Vec<Species>,  Vec<Vec<(u32,u32)>>, Vec<Axiom>){
-    let mut map = Map::new();
-    let mut rng = rand::thread_rng();
+    let mut map = Map::new(width, height);
+    // A toroidal arena has no edges to wall off, so every tile takes part in the cave generation.
+    let margin = match topology {
+        Topology::Bounded => 1,
+        Topology::Toroidal => 0,
+    };
 
     // First we completely randomize the map, setting 55% of it to be floor.
-    for y in 0..PLAY_AREA_HEIGHT {
-        for x in 0..PLAY_AREA_WIDTH {
+    for y in 0..height {
+        for x in 0..width {
             let roll = rng.gen_range(0..100);
             let idx = map.xy_idx(x, y);
-            let edge = x == 0 || y == 0 || y == PLAY_AREA_HEIGHT-1 || x == PLAY_AREA_WIDTH-1;
+            let edge = topology == Topology::Bounded && (x == 0 || y == 0 || y == height-1 || x == width-1);
             if roll > 85 || edge { map.tiles[idx] = Species::Wall }
         }
     }
     for _i in 0..15 {
         let mut newtiles = map.tiles.clone();
 
-        for y in 1..PLAY_AREA_HEIGHT-1 {
-            for x in 1..PLAY_AREA_WIDTH-1 {
+        for y in margin..height-margin {
+            for x in margin..width-margin {
                 let idx = map.xy_idx(x, y);
                 let mut neighbors = 0;
-                if map.tiles[idx - 1] == Species::Wall { neighbors += 1; }
-                if map.tiles[idx + 1] == Species::Wall { neighbors += 1; }
-                if map.tiles[idx - PLAY_AREA_WIDTH as usize] == Species::Wall { neighbors += 1; }
-                if map.tiles[idx + PLAY_AREA_WIDTH as usize] == Species::Wall { neighbors += 1; }
-                if map.tiles[idx - (PLAY_AREA_WIDTH as usize - 1)] == Species::Wall { neighbors += 1; }
-                if map.tiles[idx - (PLAY_AREA_WIDTH as usize + 1)] == Species::Wall { neighbors += 1; }
-                if map.tiles[idx + (PLAY_AREA_WIDTH as usize - 1)] == Species::Wall { neighbors += 1; }
-                if map.tiles[idx + (PLAY_AREA_WIDTH as usize + 1)] == Species::Wall { neighbors += 1; }
+                for (dx, dy) in [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)] {
+                    // Inside the margin of a bounded map this never actually wraps.
+                    let nx = (x as i32 + dx).rem_euclid(width as i32) as u32;
+                    let ny = (y as i32 + dy).rem_euclid(height as i32) as u32;
+                    if map.tiles[map.xy_idx(nx, ny)] == Species::Wall { neighbors += 1; }
+                }
 
                 if neighbors > 4 || neighbors == 0 {
                     newtiles[idx] = Species::Wall;
@@ -64,8 +223,8 @@ pub fn build_map(
     let mut catalogue = vec![Species::Wall];
     let mut locations = vec![Vec::new()];
     let mut eligible_spawns = Vec::new();
-    for y in 0..PLAY_AREA_HEIGHT {
-        for x in 0..PLAY_AREA_WIDTH {
+    for y in 0..height {
+        for x in 0..width {
             let idx = map.xy_idx(x, y);
             if map.tiles[idx] == Species::Nothing{
                 eligible_spawns.push((x,y));
@@ -75,8 +234,17 @@ pub fn build_map(
     }
     let queue_of_species = parameters.clone();
     for s in queue_of_species{
+        if eligible_spawns.is_empty() { // The caves came out too cramped for everyone, so knock down a wall. Config::check makes sure there are enough.
+            let inner = locations[0].iter().enumerate().filter(|(_, (x, y))| topology == Topology::Toroidal || (*x > 0 && *y > 0 && *x < width-1 && *y < height-1)).map(|(i, _)| i).choose(rng);
+            if let Some(i) = inner {
+                let (x, y) = locations[0].remove(i);
+                let idx = map.xy_idx(x, y);
+                map.tiles[idx] = Species::Nothing;
+                eligible_spawns.push((x, y));
+            }
+        }
         let empty_spaces = eligible_spawns.clone();
-        let (i, t) = empty_spaces.iter().enumerate().choose(&mut thread_rng()).unwrap();
+        let (i, t) = empty_spaces.iter().enumerate().choose(rng).unwrap();
         eligible_spawns.remove(i);
         let idx = map.xy_idx(t.0, t.1);
         map.tiles[idx] = s;
@@ -94,34 +262,102 @@ pub fn build_map(
     (map.tiles, catalogue, locations, map.axiom_map)
 }
 
-#[derive(Resource)]
+#[derive(Resource, Clone)]
 pub struct Map {
+    pub width: u32, // Changing these takes effect when the next map gets built.
+    pub height: u32,
+    pub topology: Topology,
+    pub out_of_bounds: OutOfBounds, // Used by the senses and Axioms reaching past the edge.
+    pub sense_shape: NeighbourhoodShape,
+    pub sense_centre: bool, // Whether the Psychics also sense the tile they are standing on.
+    pub rival_senses: bool, // Psychics and Hunters see each other. Only when there are Hunters.
+    pub energy: Option<Energy>, // None when the Psychics can act forever.
+    pub teams: bool, // Paintball: the Psychics are split between Red and Blue, and tell their paint from the other's.
     pub tiles: Vec<Species>, // The tiles on the map.
+    pub starting_tiles: Vec<Species>, // The tiles as they were on the first turn of this generation.
+    pub shipped_tiles: Vec<Species>, // Same, but for the last finished generation.
+    pub shipped_width: u32,
+    pub shipped_height: u32,
     pub axiom_map: Vec<Axiom>,
     pub population: Vec<Species>, // The list of creatures that get added on it (no walls)
+    pub scripts: Vec<ScriptRule>, // How each kind of Hylic behaves, standing still when not listed.
 
     pub catalogue: Vec<Species>, // The indexer of creature locations.
     pub locations: Vec<Vec<(u32,u32)>>,
 }
 
 impl Map{
-    fn new() -> Self{
+    pub fn new(width: u32, height: u32) -> Self{
         let mut recipe = vec![Species::Beacon];
         for _i in 0..63{
             recipe.push(Species::Psychic);
         }
-        let mut new_map = Self { tiles: Vec::with_capacity((PLAY_AREA_HEIGHT*PLAY_AREA_WIDTH) as usize), population: recipe, catalogue: Vec::new(), locations: Vec::new(), axiom_map: Vec::with_capacity((PLAY_AREA_HEIGHT*PLAY_AREA_WIDTH) as usize)};
-        for _i in 0..PLAY_AREA_HEIGHT*PLAY_AREA_WIDTH{
+        let mut new_map = Self { width, height, topology: Topology::Bounded, out_of_bounds: OutOfBounds::Wall, sense_shape: NeighbourhoodShape::Moore, sense_centre: false, rival_senses: false, energy: None, teams: false, starting_tiles: Vec::new(), shipped_tiles: Vec::new(), shipped_width: width, shipped_height: height, tiles: Vec::with_capacity((height*width) as usize), population: recipe, scripts: Vec::new(), catalogue: Vec::new(), locations: Vec::new(), axiom_map: Vec::with_capacity((height*width) as usize)};
+        for _i in 0..height*width{
             new_map.tiles.push(Species::Nothing);
             new_map.axiom_map.push(Axiom::Void);
         }
         new_map
     }
+    pub fn add_hunters(&mut self, hunters: usize) { // Some of the Psychics in the recipe become their predators.
+        for _i in 0..hunters{
+            let Some(index) = self.population.iter().rposition(|s| *s == Species::Psychic) else { break };
+            self.population[index] = Species::Hunter;
+        }
+        self.rival_senses = self.population.contains(&Species::Hunter);
+    }
+    pub fn split_teams(&mut self) { // Every other Psychic in the recipe joins each faction.
+        let mut red = true;
+        for species in self.population.iter_mut().filter(|s| **s == Species::Psychic){
+            *species = if red { Species::Red } else { Species::Blue };
+            red = !red;
+        }
+        self.teams = self.population.contains(&Species::Red);
+    }
+    pub fn script_for(&self, species: Species) -> Script {
+        self.scripts.iter().rev().find(|rule| rule.species == species).map_or(Script::Still, |rule| rule.script.clone()) // The last one given wins.
+    }
     pub fn xy_idx(&self, x: u32, y: u32) -> usize {
-        (y as usize * PLAY_AREA_WIDTH as usize) + x as usize
+        (y as usize * self.width as usize) + x as usize
+    }
+    pub fn sense_neighbourhood(&self, range: i32) -> Neighbourhood {
+        let neighbourhood = match self.sense_shape {
+            NeighbourhoodShape::Moore => Neighbourhood::moore(range),
+            NeighbourhoodShape::VonNeumann => Neighbourhood::von_neumann(range),
+        };
+        let neighbourhood = neighbourhood.with_out_of_bounds(self.out_of_bounds);
+        match self.sense_centre {
+            true => neighbourhood.with_centre(),
+            false => neighbourhood,
+        }
+    }
+    pub fn distance(&self, a: (u32, u32), b: (u32, u32)) -> u32 { // In steps, the short way round on a torus.
+        let gap = |a: u32, b: u32, size: u32| match self.topology {
+            Topology::Toroidal => a.abs_diff(b).min(size - a.abs_diff(b)),
+            Topology::Bounded => a.abs_diff(b),
+        };
+        gap(a.0, b.0, self.width) + gap(a.1, b.1, self.height)
+    }
+    pub fn resolve(&self, x: i32, y: i32, out_of_bounds: OutOfBounds) -> Option<(u32, u32)> { // None if the coordinates fell off the edge.
+        if (0..self.width as i32).contains(&x) && (0..self.height as i32).contains(&y) {
+            return Some((x as u32, y as u32));
+        }
+        if self.topology == Topology::Toroidal || out_of_bounds == OutOfBounds::Wrap {
+            return Some((x.rem_euclid(self.width as i32) as u32, y.rem_euclid(self.height as i32) as u32));
+        }
+        None
     }
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-pub fn xy_idx(x: u32, y: u32) -> usize {
-    (y as usize * PLAY_AREA_WIDTH as usize) + x as usize
-}
\ No newline at end of file
+    #[test]
+    fn distance_wraps_only_on_a_torus() {
+        let mut map = Map::new(10, 6);
+        assert_eq!(map.distance((0, 0), (9, 5)), 14);
+        map.topology = Topology::Toroidal;
+        assert_eq!(map.distance((0, 0), (9, 5)), 2);
+        assert_eq!(map.distance((2, 3), (7, 0)), 8);
+    }
+}
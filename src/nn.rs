@@ -13,7 +13,7 @@ struct Layer {
 }
 
 impl Net {
-    pub fn new(layer_sizes: Vec<usize>) -> Self {
+    pub fn new(layer_sizes: Vec<usize>, rng: &mut impl Rng) -> Self {
         if layer_sizes.len() < 2 {
             panic!("Need at least 2 layers");
         }
@@ -28,7 +28,7 @@ impl Net {
         let mut prev_layer_size = first_layer_size;
 
         for &layer_size in layer_sizes[1..].iter() {
-            layers.push(Layer::new(layer_size, prev_layer_size));
+            layers.push(Layer::new(layer_size, prev_layer_size, rng));
             prev_layer_size = layer_size;
         }
 
@@ -37,7 +37,11 @@ impl Net {
             n_inputs: first_layer_size,
         }
     }
-    pub fn decide(&self, inputs: &Vec<f64>) -> Vec<f64> {
+    pub fn decide(&self, inputs: &[f64]) -> Vec<f64> {
+        let outputs = self.activations(inputs);
+        outputs[outputs.len()-1].clone()
+    }
+    pub fn activations(&self, inputs: &[f64]) -> Vec<Vec<f64>> { // The values of every node, layer by layer, starting with the inputs.
         if inputs.len() != self.n_inputs {
             panic!("Bad input size");
         }
@@ -50,21 +54,44 @@ impl Net {
         }
 
         let mut outputs = Vec::new();
-        outputs.push(inputs.clone());
+        outputs.push(inputs.to_vec());
         for (layer_index, layer) in self.layers.iter().enumerate() {
             let layer_results = layer.predict(&outputs[layer_index]);
             outputs.push(layer_results);
         }
-        outputs[outputs.len()-1].clone()
+        outputs
+    }
+    pub fn from_weights(n_inputs: usize, layers: Vec<Vec<Vec<f64>>>) -> Self { // The other way around from weights(), for loading saved brains.
+        Self {
+            n_inputs,
+            layers: layers.into_iter().map(|nodes| Layer{nodes}).collect(),
+        }
+    }
+    pub fn weights(&self) -> Vec<&Vec<Vec<f64>>> { // For each layer, for each node: the bias, then one weight per node of the previous layer.
+        self.layers.iter().map(|l| &l.nodes).collect()
+    }
+    pub fn shape(&self) -> Vec<usize> { // How many nodes in each layer, inputs included.
+        let mut sizes = vec![self.n_inputs];
+        sizes.extend(self.layers.iter().map(|l| l.nodes.len()));
+        sizes
+    }
+    pub fn distance(&self, other: &Net) -> f64 { // Average gap between matching weights. Brains of different shapes are infinitely far apart.
+        let (mine, theirs): (Vec<f64>, Vec<f64>) = (self.flat_weights(), other.flat_weights());
+        if self.n_inputs != other.n_inputs || mine.len() != theirs.len() || mine.is_empty() {
+            return f64::INFINITY;
+        }
+        mine.iter().zip(theirs.iter()).map(|(a, b)| (a - b).abs()).sum::<f64>() / mine.len() as f64
+    }
+    fn flat_weights(&self) -> Vec<f64> {
+        self.layers.iter().flat_map(|l| l.nodes.iter().flatten().copied()).collect()
     }
-    pub fn mutate(&mut self) {
-        self.layers.iter_mut().for_each(|l| l.mutate());
+    pub fn mutate(&mut self, rate: f64, strength: f64, rng: &mut impl Rng) { // Each weight has a "rate" chance to be nudged by up to "strength" either way.
+        self.layers.iter_mut().for_each(|l| l.mutate(rate, strength, rng));
     }
 }
 
 impl Layer{
-    fn new(layer_size: usize, prev_layer_size: usize) -> Self {
-        let mut rng = rand::thread_rng();
+    fn new(layer_size: usize, prev_layer_size: usize, rng: &mut impl Rng) -> Self {
         let mut nodes: Vec<Vec<f64>> = Vec::with_capacity(layer_size);
 
         for _ in 0..layer_size {
@@ -85,15 +112,14 @@ impl Layer{
         }
         layer_results
     }
-    fn mutate(&mut self) {
-        let mut rng = rand::thread_rng();
+    fn mutate(&mut self, rate: f64, strength: f64, rng: &mut impl Rng) {
         for n in self.nodes.iter_mut() {
             for val in n.iter_mut() {
-                if rng.gen_range(0.0..1.0) >= 5.0 {
+                if rng.gen_range(0.0..1.0) >= rate || strength <= 0. {
                     continue;
                 }
 
-                *val += rng.gen_range(-0.5..0.5) as f64;
+                *val += rng.gen_range(-strength..strength);
             }
         }
     }
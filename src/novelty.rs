@@ -0,0 +1,179 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+use crate::{axiom::Axiom, map::Map, psychics::{Soul, Trace}, simulation::{EvolutionSettings, SimulationSettings}};
+
+const NEIGHBOURS: usize = 5; // The k in k-nearest neighbours.
+const ARCHIVE_PER_GENERATION: usize = 2; // How many of the most novel get remembered each generation.
+const ARCHIVE_LIMIT: usize = 500; // The oldest are forgotten past this.
+
+#[derive(Clone, Debug)]
+pub struct Behaviour { // What a Psychic did, regardless of how well it did it.
+    pub final_position: Vec2, // Between 0 and 1 on both axes.
+    pub visited: Vec<usize>, // Sorted tile indices.
+    pub actions: Vec<f32>, // How often each of its action choices got picked, adding up to 1.
+}
+
+impl Behaviour {
+    pub fn of(
+        positions: &[(u32, u32)],
+        actions: &[Axiom],
+        action_choices: &[Axiom],
+        map: &Map,
+    ) -> Option<Self> {
+        let &(x, y) = positions.last()?;
+        let final_position = Vec2::new(x as f32 / map.width.max(1) as f32, y as f32 / map.height.max(1) as f32);
+        let mut visited: Vec<usize> = positions.iter().map(|(x, y)| map.xy_idx(*x, *y)).collect();
+        visited.sort_unstable();
+        visited.dedup();
+        let mut histogram = vec![0.; action_choices.len()];
+        for action in actions {
+            if let Some(i) = action_choices.iter().position(|a| a == action) {
+                histogram[i] += 1.;
+            }
+        }
+        let total: f32 = histogram.iter().sum();
+        if total > 0. {
+            histogram.iter_mut().for_each(|h| *h /= total);
+        }
+        Some(Self{final_position, visited, actions: histogram})
+    }
+    pub fn distance(&self, other: &Behaviour) -> f32 { // Each part is between 0 and 1, and so is their average.
+        let position = self.final_position.distance(other.final_position) / std::f32::consts::SQRT_2;
+        let visited = jaccard_distance(&self.visited, &other.visited);
+        let actions = self.actions.iter().zip(other.actions.iter()).map(|(a, b)| (a - b).abs()).sum::<f32>() / 2.;
+        (position + visited + actions) / 3.
+    }
+}
+
+fn jaccard_distance( // 0 for the same tiles, 1 for nothing in common. Both must be sorted.
+    a: &[usize],
+    b: &[usize],
+) -> f32 {
+    let (mut i, mut j, mut shared) = (0, 0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                shared += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    let union = a.len() + b.len() - shared;
+    if union == 0 { 0. } else { 1. - shared as f32 / union as f32 }
+}
+
+#[derive(Resource, Default)]
+pub struct NoveltyArchive { // Behaviours from past generations, so going back to an old trick is not novel.
+    pub behaviours: VecDeque<Behaviour>,
+}
+
+pub fn score_novelty( // Runs once a generation is fully simulated, before it gets evolved.
+    config: Res<SimulationSettings>,
+    settings: Res<EvolutionSettings>,
+    map: Res<Map>,
+    mut archive: ResMut<NoveltyArchive>,
+    mut psychics: Query<(&mut Soul, &Trace)>,
+){
+    if config.current_turn < config.max_turn_number {
+        return;
+    }
+    if settings.novelty_weight <= 0. {
+        return; // Pure objective fitness, no need to keep an archive around.
+    }
+    let behaviours: Vec<Option<Behaviour>> = psychics.iter().map(|(soul, trace)| Behaviour::of(&trace.positions, &trace.actions, &soul.action_choices, &map)).collect();
+    let mut scored = Vec::new();
+    for ((mut soul, _), behaviour) in psychics.iter_mut().zip(behaviours.iter()) {
+        let Some(behaviour) = behaviour else {
+            soul.novelty = 0.;
+            continue;
+        };
+        let distances: Vec<f32> = behaviours.iter().flatten()
+            .chain(archive.behaviours.iter())
+            .map(|other| behaviour.distance(other))
+            .collect();
+        soul.novelty = sparseness(distances);
+        scored.push((soul.novelty, behaviour.clone()));
+    }
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+    for (_, behaviour) in scored.into_iter().take(ARCHIVE_PER_GENERATION) {
+        archive.behaviours.push_back(behaviour);
+    }
+    while archive.behaviours.len() > ARCHIVE_LIMIT {
+        archive.behaviours.pop_front();
+    }
+}
+
+pub fn sparseness( // Average distance to the k nearest neighbours, from the distances to everyone including itself.
+    mut distances: Vec<f32>,
+) -> f32 {
+    distances.sort_by(|a, b| a.total_cmp(b));
+    let nearest = &distances[1.min(distances.len())..]; // The first one is itself.
+    let k = NEIGHBOURS.min(nearest.len()).max(1);
+    nearest.iter().take(k).sum::<f32>() / k as f32
+}
+
+pub fn blend_scores( // Both sides are scaled to the best of the generation, then mixed. 0 is all fitness, 1 all novelty.
+    fitnesses: &[f32],
+    novelties: &[f32],
+    novelty_weight: f32,
+) -> Vec<f32> {
+    let weight = novelty_weight.clamp(0., 1.);
+    if weight == 0. {
+        return fitnesses.to_vec(); // Exactly the old behaviour.
+    }
+    let best_fitness = fitnesses.iter().copied().fold(0., f32::max).max(f32::EPSILON);
+    let best_novelty = novelties.iter().copied().fold(0., f32::max).max(f32::EPSILON);
+    fitnesses.iter().zip(novelties.iter())
+        .map(|(f, n)| ((1. - weight) * f / best_fitness + weight * n / best_novelty).max(0.001)) // The roulette needs something to spin.
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn behaviour(x: f32, y: f32, visited: Vec<usize>, actions: Vec<f32>) -> Behaviour {
+        Behaviour{final_position: Vec2::new(x, y), visited, actions}
+    }
+
+    #[test]
+    fn jaccard_counts_shared_tiles() {
+        assert_eq!(jaccard_distance(&[1, 2, 3], &[1, 2, 3]), 0.);
+        assert_eq!(jaccard_distance(&[1, 2], &[3, 4]), 1.);
+        assert_eq!(jaccard_distance(&[1, 2, 3], &[2, 3, 4]), 0.5);
+        assert_eq!(jaccard_distance(&[], &[]), 0.);
+    }
+
+    #[test]
+    fn distance_goes_from_zero_to_one() {
+        let a = behaviour(0., 0., vec![0, 1], vec![1., 0.]);
+        let b = behaviour(1., 1., vec![2, 3], vec![0., 1.]);
+        assert_eq!(a.distance(&a), 0.);
+        assert!((a.distance(&b) - 1.).abs() < 1e-6);
+        assert_eq!(a.distance(&b), b.distance(&a));
+    }
+
+    #[test]
+    fn behaviour_of_a_trace() {
+        let map = Map::new(4, 4);
+        let moves = [Axiom::Move{dx: 1, dy: 0}, Axiom::Move{dx: 0, dy: 1}];
+        let seen = Behaviour::of(&[(0, 0), (1, 0), (1, 0)], &[moves[0], moves[0], moves[1], Axiom::Void], &moves, &map).unwrap();
+        assert_eq!(seen.final_position, Vec2::new(0.25, 0.));
+        assert_eq!(seen.visited, vec![0, 1]);
+        assert_eq!(seen.actions, vec![2. / 3., 1. / 3.]);
+        assert!(Behaviour::of(&[], &[], &moves, &map).is_none());
+    }
+
+    #[test]
+    fn sparseness_skips_itself_and_keeps_the_nearest() {
+        assert_eq!(sparseness(vec![0.]), 0.); // Alone in the world.
+        assert!((sparseness(vec![0.4, 0., 0.2]) - 0.3).abs() < 1e-6);
+        let crowd: Vec<f32> = (0..=NEIGHBOURS + 3).map(|i| i as f32).collect(); // Itself, then 1, 2, 3...
+        assert_eq!(sparseness(crowd), (1..=NEIGHBOURS).sum::<usize>() as f32 / NEIGHBOURS as f32);
+    }
+}
@@ -0,0 +1,295 @@
+use bevy::{prelude::*, sprite::Anchor};
+
+use crate::{axiom::Axiom, compare::Compared, inspector::Inspected, map::{Map, OutOfBounds, Topology}, psychics::FinishedTrace, theatre::{TILE_SIZE, TheatreSettings}};
+
+pub struct OverlayPlugin;
+
+impl Plugin for OverlayPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Overlay{mode: OverlayMode::Off, dimensions: (0, 0)});
+        app.add_systems(Startup, draw_overlay_label);
+        app.add_systems(Update, (cycle_overlay, spawn_overlay_tiles, paint_overlay).chain());
+        app.insert_resource(Trails{style: TrailStyle::Off, colouring: TrailColouring::Individual, length: 10});
+        app.add_systems(Update, (trail_controls, draw_trails).chain());
+    }
+}
+
+const OVERLAY_DEPTH: f32 = 2.; // Above the creatures.
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OverlayMode {
+    Off,
+    Visits, // How often each tile was stood on by any Psychic.
+    SelectedVisits, // Same, only for the inspected Psychic.
+    Paint, // Where walls got painted.
+    Collisions, // Where Psychics bumped into something while trying to move.
+    Spawns, // Where Psychics started.
+}
+
+impl OverlayMode {
+    fn next(self) -> Self {
+        match self {
+            OverlayMode::Off => OverlayMode::Visits,
+            OverlayMode::Visits => OverlayMode::SelectedVisits,
+            OverlayMode::SelectedVisits => OverlayMode::Paint,
+            OverlayMode::Paint => OverlayMode::Collisions,
+            OverlayMode::Collisions => OverlayMode::Spawns,
+            OverlayMode::Spawns => OverlayMode::Off,
+        }
+    }
+    fn tint(self) -> Color {
+        match self {
+            OverlayMode::Off => Color::NONE,
+            OverlayMode::Visits => Color::rgb(1., 0.5, 0.),
+            OverlayMode::SelectedVisits => Color::rgb(1., 0.9, 0.2),
+            OverlayMode::Paint => Color::rgb(1., 0.4, 0.8),
+            OverlayMode::Collisions => Color::rgb(1., 0.1, 0.1),
+            OverlayMode::Spawns => Color::rgb(0.2, 1., 0.3),
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct Overlay {
+    pub mode: OverlayMode,
+    dimensions: (u32, u32), // Of the tile grid currently spawned.
+}
+
+#[derive(Component)]
+pub struct OverlayTile {
+    x: u32,
+    y: u32,
+}
+
+#[derive(Component)]
+pub struct OverlayLabel;
+
+fn draw_overlay_label(
+    mut commands: Commands,
+){
+    commands.spawn((TextBundle::from_section(
+        "",
+        TextStyle {
+            font_size: 16.,
+            color: Color::WHITE,
+            ..default()
+        },
+    ).with_style(Style {
+        position_type: PositionType::Absolute,
+        top: Val::Px(4.),
+        left: Val::Px(4.),
+        ..default()
+    }), OverlayLabel));
+}
+
+fn cycle_overlay( // H switches to the next heatmap.
+    keys: Res<Input<KeyCode>>,
+    mut overlay: ResMut<Overlay>,
+    mut label: Query<&mut Text, With<OverlayLabel>>,
+){
+    if keys.just_pressed(KeyCode::H) {
+        overlay.mode = overlay.mode.next();
+    }
+    if !overlay.is_changed() {
+        return;
+    }
+    for mut text in label.iter_mut(){
+        text.sections[0].value = match overlay.mode {
+            OverlayMode::Off => String::new(),
+            mode => format!("Heatmap: {mode:?}"),
+        };
+    }
+}
+
+fn spawn_overlay_tiles( // One tinted square per tile, respawned whenever the arena on show changes size.
+    mut commands: Commands,
+    map: Res<Map>,
+    mut overlay: ResMut<Overlay>,
+    tiles: Query<Entity, With<OverlayTile>>,
+){
+    let (width, height) = (map.shipped_width, map.shipped_height); // The traces are from that arena, not the one being trained on.
+    if overlay.dimensions == (width, height) {
+        return;
+    }
+    for tile in tiles.iter(){
+        commands.entity(tile).despawn();
+    }
+    for y in 0..height {
+        for x in 0..width {
+            commands.spawn((SpriteBundle {
+                sprite: Sprite {
+                    color: Color::NONE,
+                    custom_size: Some(Vec2::splat(TILE_SIZE)),
+                    anchor: Anchor::BottomLeft,
+                    ..default()
+                },
+                transform: Transform::from_translation(Vec3::new(TILE_SIZE * x as f32, TILE_SIZE * y as f32, OVERLAY_DEPTH)),
+                visibility: Visibility::Hidden,
+                ..default()
+            }, OverlayTile{x, y}));
+        }
+    }
+    overlay.dimensions = (width, height);
+}
+
+fn paint_overlay(
+    overlay: Res<Overlay>,
+    inspected: Res<Inspected>,
+    map: Res<Map>,
+    actors: Query<(Entity, &FinishedTrace), Without<Compared>>,
+    changed: Query<(), Changed<FinishedTrace>>,
+    added_tiles: Query<(), Added<OverlayTile>>,
+    mut tiles: Query<(&OverlayTile, &mut Sprite, &mut Visibility)>,
+){
+    if !overlay.is_changed() && !inspected.is_changed() && changed.is_empty() && added_tiles.is_empty() {
+        return;
+    }
+    let counts = count_events(overlay.mode, inspected.actor, &map, actors.iter());
+    let max = counts.iter().copied().max().unwrap_or(0).max(1) as f32;
+    for (tile, mut sprite, mut visibility) in tiles.iter_mut(){
+        let idx = (tile.y * map.shipped_width + tile.x) as usize;
+        let count = counts.get(idx).copied().unwrap_or(0);
+        if count == 0 {
+            *visibility = Visibility::Hidden;
+            continue;
+        }
+        *visibility = Visibility::Visible;
+        sprite.color = overlay.mode.tint().with_a(0.15 + 0.6 * count as f32 / max);
+    }
+}
+
+fn count_events<'a>( // How many times something happened on each tile of the shipped arena, indexed like Map.shipped_tiles.
+    mode: OverlayMode,
+    selected: Option<Entity>,
+    map: &Map,
+    actors: impl Iterator<Item = (Entity, &'a FinishedTrace)>,
+) -> Vec<u32> {
+    let (width, height) = (map.shipped_width, map.shipped_height);
+    let mut counts = vec![0; (width * height) as usize];
+    let mut mark = |(x, y): (u32, u32)| {
+        if x < width && y < height {
+            counts[(y * width + x) as usize] += 1;
+        }
+    };
+    for (entity, trace) in actors {
+        let psychic = trace.is_psychic();
+        match mode {
+            OverlayMode::Off => (),
+            OverlayMode::Visits if psychic => trace.positions.iter().for_each(|p| mark(*p)),
+            OverlayMode::SelectedVisits if selected == Some(entity) => trace.positions.iter().for_each(|p| mark(*p)),
+            OverlayMode::Spawns if psychic => trace.positions.first().into_iter().for_each(|p| mark(*p)),
+            OverlayMode::Paint => {
+                for (t, pair) in trace.identity.windows(2).enumerate() {
+                    if pair[1].is_paint() && pair[0] != pair[1] { // Painting over the other faction counts too.
+                        if let Some(p) = trace.positions.get(t + 1) { mark(*p) }
+                    }
+                }
+            },
+            OverlayMode::Collisions if psychic => {
+                for (t, action) in trace.actions.iter().enumerate() {
+                    let Axiom::Move { dx, dy } = action else { continue };
+                    let (Some(before), Some(after)) = (trace.positions.get(t), trace.positions.get(t + 1)) else { continue };
+                    if (*dx, *dy) != (0, 0) && before == after {
+                        let (x, y) = (before.0 as i32 + dx, before.1 as i32 + dy);
+                        let inside = (0..width as i32).contains(&x) && (0..height as i32).contains(&y);
+                        let wraps = map.topology == Topology::Toroidal || map.out_of_bounds == OutOfBounds::Wrap;
+                        match (inside, wraps) { // The wall it bumped into, or itself if that was the edge.
+                            (true, _) => mark((x as u32, y as u32)),
+                            (false, true) => mark((x.rem_euclid(width as i32) as u32, y.rem_euclid(height as i32) as u32)),
+                            (false, false) => mark(*before),
+                        }
+                    }
+                }
+            },
+            _ => (),
+        }
+    }
+    counts
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TrailStyle {
+    Off,
+    Lines,
+    Dots,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TrailColouring {
+    Individual, // Each Psychic gets its own colour.
+    FitnessRank, // Red for the worst, green for the best.
+}
+
+#[derive(Resource)]
+pub struct Trails { // Fading lines behind each Psychic, great for spotting a Tango.
+    pub style: TrailStyle,
+    pub colouring: TrailColouring,
+    pub length: usize, // In turns.
+}
+
+fn trail_controls( // T changes the style, G the colouring, - and = the length.
+    keys: Res<Input<KeyCode>>,
+    mut trails: ResMut<Trails>,
+){
+    if keys.just_pressed(KeyCode::T) {
+        trails.style = match trails.style {
+            TrailStyle::Off => TrailStyle::Lines,
+            TrailStyle::Lines => TrailStyle::Dots,
+            TrailStyle::Dots => TrailStyle::Off,
+        };
+    }
+    if keys.just_pressed(KeyCode::G) {
+        trails.colouring = match trails.colouring {
+            TrailColouring::Individual => TrailColouring::FitnessRank,
+            TrailColouring::FitnessRank => TrailColouring::Individual,
+        };
+    }
+    if keys.just_pressed(KeyCode::Minus) {
+        trails.length = trails.length.saturating_sub(5).max(2);
+    }
+    if keys.just_pressed(KeyCode::Equals) {
+        trails.length = (trails.length + 5).min(100);
+    }
+}
+
+fn draw_trails(
+    trails: Res<Trails>,
+    config: Res<TheatreSettings>,
+    actors: Query<(Entity, &FinishedTrace), Without<Compared>>, // Heatmaps and trails only cover the main arena.
+    mut gizmos: Gizmos,
+){
+    if trails.style == TrailStyle::Off {
+        return;
+    }
+    let mut fitnesses: Vec<f32> = actors.iter().filter(|(_, t)| t.is_psychic()).map(|(_, t)| t.fitness).collect();
+    fitnesses.sort_by(|a, b| a.total_cmp(b));
+    let centre = |(x, y): (u32, u32)| Vec2::new((x as f32 + 0.5) * TILE_SIZE, (y as f32 + 0.5) * TILE_SIZE);
+    for (entity, trace) in actors.iter(){
+        if !trace.is_psychic() || trace.positions.is_empty() {
+            continue;
+        }
+        let colour = match trails.colouring {
+            TrailColouring::Individual => Color::hsl((entity.index() as f32 * 137.5) % 360., 0.8, 0.6),
+            TrailColouring::FitnessRank => {
+                let rank = fitnesses.partition_point(|f| *f < trace.fitness);
+                Color::hsl(120. * rank as f32 / fitnesses.len().max(2).saturating_sub(1) as f32, 0.9, 0.5)
+            },
+        };
+        let end = config.current_turn.min(trace.positions.len() - 1);
+        let start = end.saturating_sub(trails.length);
+        let recent = &trace.positions[start..=end];
+        if trails.style == TrailStyle::Dots { // One per position, the current tile included.
+            for (i, position) in recent.iter().enumerate(){
+                gizmos.circle_2d(centre(*position), 2., colour.with_a((i + 1) as f32 / recent.len() as f32));
+            }
+            continue;
+        }
+        for (i, pair) in recent.windows(2).enumerate(){
+            let fade = (i + 1) as f32 / recent.len() as f32;
+            let (from, to) = (centre(pair[0]), centre(pair[1]));
+            if from.distance(to) < TILE_SIZE * 1.5 { // Do not draw lines across the whole arena when wrapping around.
+                gizmos.line_2d(from, to, colour.with_a(fade));
+            }
+        }
+    }
+}
@@ -0,0 +1,24 @@
+use crate::map::Species;
+
+pub const TEAMS: [Species; 2] = [Species::Red, Species::Blue];
+
+pub fn territory( // Share of the paintable tiles held by each faction, walls left bare count for no one.
+    tiles: &[Species],
+) -> Vec<(Species, f32)> {
+    let paintable = tiles.iter().filter(|t| **t == Species::Wall || t.is_paint()).count().max(1);
+    TEAMS.iter().map(|team| {
+        let held = tiles.iter().filter(|t| **t == team.paint()).count();
+        (*team, held as f32 / paintable as f32)
+    }).collect()
+}
+
+pub fn share_of(
+    territory: &[(Species, f32)],
+    team: Species,
+) -> f32 {
+    territory.iter().find(|(t, _)| *t == team).map_or(0., |(_, share)| *share)
+}
+
+pub fn describe_territory(territory: &[(Species, f32)]) -> String {
+    territory.iter().map(|(team, share)| format!("{:?} {:.0}%", team, share * 100.)).collect::<Vec<_>>().join(", ")
+}
@@ -0,0 +1,253 @@
+use bevy::prelude::*;
+
+use crate::{simulation::{EvolutionSettings, NextEvolutionSettings, Selection}, theatre::{SPEED_PRESETS, TheatreSettings}};
+
+pub struct PanelPlugin;
+
+impl Plugin for PanelPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, draw_settings_panel);
+        app.add_systems(Update, (toggle_settings_panel, press_parameter_buttons, update_parameter_values).chain());
+    }
+}
+
+const TURNS_PER_FRAME_PRESETS: [usize; 7] = [1, 2, 5, 10, 20, 50, 100];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Parameter {
+    MutationRate,
+    MutationStrength,
+    Selection,
+    PaintReward,
+    WanderBonus,
+    VarietyBonus,
+    PainterBonus,
+    CatchReward,
+    EvadeReward,
+    TerritoryReward,
+    NoveltyWeight,
+    TargetSpecies,
+    TurnsPerFrame,
+    TurnsPerGeneration,
+    TheatreSpeed, // The only one applied right away, it does not change the training.
+}
+
+const PARAMETERS: [Parameter; 15] = [
+    Parameter::MutationRate,
+    Parameter::MutationStrength,
+    Parameter::Selection,
+    Parameter::PaintReward,
+    Parameter::WanderBonus,
+    Parameter::VarietyBonus,
+    Parameter::PainterBonus,
+    Parameter::CatchReward,
+    Parameter::EvadeReward,
+    Parameter::TerritoryReward,
+    Parameter::NoveltyWeight,
+    Parameter::TargetSpecies,
+    Parameter::TurnsPerFrame,
+    Parameter::TurnsPerGeneration,
+    Parameter::TheatreSpeed,
+];
+
+impl Parameter {
+    fn label(self) -> &'static str {
+        match self {
+            Parameter::MutationRate => "Mutation rate",
+            Parameter::MutationStrength => "Mutation strength",
+            Parameter::Selection => "Selection",
+            Parameter::PaintReward => "Paint reward",
+            Parameter::WanderBonus => "Wander bonus",
+            Parameter::VarietyBonus => "Variety bonus",
+            Parameter::PainterBonus => "Painter bonus",
+            Parameter::CatchReward => "Catch reward",
+            Parameter::EvadeReward => "Evade reward",
+            Parameter::TerritoryReward => "Territory reward",
+            Parameter::NoveltyWeight => "Novelty weight",
+            Parameter::TargetSpecies => "Species",
+            Parameter::TurnsPerFrame => "Turns per frame",
+            Parameter::TurnsPerGeneration => "Turns per generation",
+            Parameter::TheatreSpeed => "Theatre ms per turn",
+        }
+    }
+    fn value(self, settings: &EvolutionSettings, theatre: &TheatreSettings) -> String {
+        match self {
+            Parameter::MutationRate => format!("{:.2}", settings.mutation_rate),
+            Parameter::MutationStrength => format!("{:.2}", settings.mutation_strength),
+            Parameter::Selection => format!("{:?}", settings.selection),
+            Parameter::PaintReward => format!("{:.1}", settings.fitness.paint),
+            Parameter::WanderBonus => format!("x{:.0}", settings.fitness.wander),
+            Parameter::VarietyBonus => format!("x{:.0}", settings.fitness.variety),
+            Parameter::PainterBonus => format!("x{:.0}", settings.fitness.painter),
+            Parameter::CatchReward => format!("{:.1}", settings.fitness.catch),
+            Parameter::EvadeReward => format!("{:.1}", settings.fitness.evade),
+            Parameter::TerritoryReward => format!("{:.0}", settings.fitness.territory),
+            Parameter::NoveltyWeight => format!("{:.1}", settings.novelty_weight),
+            Parameter::TargetSpecies => match settings.target_species {
+                0 => "off".to_string(),
+                n => n.to_string(),
+            },
+            Parameter::TurnsPerFrame => settings.turns_per_frame.to_string(),
+            Parameter::TurnsPerGeneration => settings.max_turn_number.to_string(),
+            Parameter::TheatreSpeed => SPEED_PRESETS[theatre.speed].to_string(),
+        }
+    }
+    fn adjust(self, settings: &mut EvolutionSettings, theatre: &mut TheatreSettings, step: i32) {
+        let nudge = |value: f32, by: f32| ((value + by * step as f32) / by).round() * by; // Rounded, so repeated clicks do not drift.
+        match self {
+            Parameter::MutationRate => settings.mutation_rate = nudge(settings.mutation_rate as f32, 0.05).clamp(0., 1.) as f64,
+            Parameter::MutationStrength => settings.mutation_strength = nudge(settings.mutation_strength as f32, 0.05).clamp(0., 2.) as f64,
+            Parameter::Selection => settings.selection = match (settings.selection, step > 0) {
+                (Selection::Roulette, true) | (Selection::Truncation, false) => Selection::Tournament,
+                (Selection::Tournament, true) | (Selection::Pareto, false) => Selection::Truncation,
+                (Selection::Truncation, true) | (Selection::Roulette, false) => Selection::Pareto,
+                (Selection::Pareto, true) | (Selection::Tournament, false) => Selection::Roulette,
+            },
+            Parameter::PaintReward => settings.fitness.paint = nudge(settings.fitness.paint, 0.5).clamp(0., 20.),
+            Parameter::WanderBonus => settings.fitness.wander = nudge(settings.fitness.wander, 1.).clamp(1., 50.),
+            Parameter::VarietyBonus => settings.fitness.variety = nudge(settings.fitness.variety, 10.).clamp(1., 1000.),
+            Parameter::PainterBonus => settings.fitness.painter = nudge(settings.fitness.painter, 5.).clamp(1., 200.),
+            Parameter::CatchReward => settings.fitness.catch = nudge(settings.fitness.catch, 1.).clamp(0., 100.),
+            Parameter::EvadeReward => settings.fitness.evade = nudge(settings.fitness.evade, 0.1).clamp(0., 10.),
+            Parameter::TerritoryReward => settings.fitness.territory = nudge(settings.fitness.territory, 10.).clamp(0., 1000.),
+            Parameter::NoveltyWeight => settings.novelty_weight = nudge(settings.novelty_weight, 0.1).clamp(0., 1.),
+            Parameter::TargetSpecies => settings.target_species = (settings.target_species as i32 + step).clamp(0, 20) as usize,
+            Parameter::TurnsPerFrame => {
+                let current = TURNS_PER_FRAME_PRESETS.iter().position(|t| *t >= settings.turns_per_frame).unwrap_or(TURNS_PER_FRAME_PRESETS.len() - 1);
+                let next = (current as i32 + step).clamp(0, TURNS_PER_FRAME_PRESETS.len() as i32 - 1);
+                settings.turns_per_frame = TURNS_PER_FRAME_PRESETS[next as usize];
+            },
+            Parameter::TurnsPerGeneration => settings.max_turn_number = (settings.max_turn_number as i32 + 10 * step).clamp(10, 1000) as usize,
+            Parameter::TheatreSpeed => { // Faster is further into SPEED_PRESETS, but + should mean more milliseconds.
+                let speed = (theatre.speed as i32 - step).clamp(0, SPEED_PRESETS.len() as i32 - 1) as usize;
+                theatre.set_speed(speed);
+            },
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct SettingsPanel;
+
+#[derive(Component)]
+pub struct ParameterButton {
+    parameter: Parameter,
+    step: i32,
+}
+
+#[derive(Component)]
+pub struct ParameterValue {
+    parameter: Parameter,
+}
+
+fn draw_settings_panel(
+    mut commands: Commands,
+){
+    let text_style = TextStyle {
+        font_size: 14.,
+        color: Color::WHITE,
+        ..default()
+    };
+    commands.spawn((NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(44.),
+            left: Val::Px(4.),
+            flex_direction: FlexDirection::Column,
+            padding: UiRect::all(Val::Px(6.)),
+            row_gap: Val::Px(2.),
+            ..default()
+        },
+        background_color: Color::rgba(0., 0., 0., 0.8).into(),
+        visibility: Visibility::Hidden,
+        ..default()
+    }, Interaction::default(), SettingsPanel)).with_children(|panel| { // The Interaction keeps clicks on the panel away from the arena.
+        panel.spawn(TextBundle::from_section("Settings (Tab)", text_style.clone()));
+        for parameter in PARAMETERS {
+            panel.spawn(NodeBundle {
+                style: Style {
+                    flex_direction: FlexDirection::Row,
+                    align_items: AlignItems::Center,
+                    column_gap: Val::Px(4.),
+                    ..default()
+                },
+                ..default()
+            }).with_children(|row| {
+                row.spawn(TextBundle::from_section(parameter.label(), text_style.clone()).with_style(Style {
+                    width: Val::Px(150.),
+                    ..default()
+                }));
+                for (step, sign) in [(-1, "-"), (1, "+")] {
+                    if step == 1 {
+                        row.spawn((TextBundle::from_section("", text_style.clone()).with_style(Style {
+                            width: Val::Px(90.),
+                            ..default()
+                        }), ParameterValue{parameter}));
+                    }
+                    row.spawn((ButtonBundle {
+                        style: Style {
+                            width: Val::Px(18.),
+                            height: Val::Px(18.),
+                            justify_content: JustifyContent::Center,
+                            align_items: AlignItems::Center,
+                            ..default()
+                        },
+                        background_color: Color::rgb(0.3, 0.3, 0.3).into(),
+                        ..default()
+                    }, ParameterButton{parameter, step})).with_children(|button| {
+                        button.spawn(TextBundle::from_section(sign, text_style.clone()));
+                    });
+                }
+            });
+        }
+        panel.spawn(TextBundle::from_section("* applies at the next generation", text_style.clone()));
+    });
+}
+
+fn toggle_settings_panel( // Tab shows or hides it.
+    keys: Res<Input<KeyCode>>,
+    mut panel: Query<&mut Visibility, With<SettingsPanel>>,
+){
+    if !keys.just_pressed(KeyCode::Tab) {
+        return;
+    }
+    for mut visibility in panel.iter_mut(){
+        *visibility = match *visibility {
+            Visibility::Hidden => Visibility::Visible,
+            _ => Visibility::Hidden,
+        };
+    }
+}
+
+fn press_parameter_buttons(
+    mut buttons: Query<(&Interaction, &ParameterButton, &mut BackgroundColor), Changed<Interaction>>,
+    mut next: ResMut<NextEvolutionSettings>,
+    mut theatre: ResMut<TheatreSettings>,
+){
+    for (interaction, button, mut colour) in buttons.iter_mut(){
+        *colour = match interaction {
+            Interaction::Pressed => Color::rgb(0.9, 0.4, 0.7).into(),
+            Interaction::Hovered => Color::rgb(0.45, 0.45, 0.45).into(),
+            Interaction::None => Color::rgb(0.3, 0.3, 0.3).into(),
+        };
+        if *interaction == Interaction::Pressed {
+            button.parameter.adjust(&mut next.0, &mut theatre, button.step);
+        }
+    }
+}
+
+fn update_parameter_values( // A star marks what is still waiting for the next generation.
+    current: Res<EvolutionSettings>,
+    next: Res<NextEvolutionSettings>,
+    theatre: Res<TheatreSettings>,
+    mut values: Query<(&mut Text, &ParameterValue)>,
+){
+    if !current.is_changed() && !next.is_changed() && !theatre.is_changed() {
+        return;
+    }
+    for (mut text, value) in values.iter_mut(){
+        let wanted = value.parameter.value(&next.0, &theatre);
+        let pending = wanted != value.parameter.value(&current, &theatre);
+        text.sections[0].value = format!("{wanted}{}", if pending { " *" } else { "" });
+    }
+}
@@ -0,0 +1,289 @@
+use bevy::prelude::*;
+use rand::Rng;
+
+use crate::{compare::Compared, inspector::Inspected, map::{Map, Species}, nn::Net, psychics::FinishedTrace, theatre::TILE_SIZE};
+
+pub struct ParetoPlugin;
+
+impl Plugin for ParetoPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ParetoView{axes: None});
+        app.add_systems(Startup, draw_pareto_label);
+        app.add_systems(Update, (cycle_pareto_view, draw_pareto_front).chain());
+    }
+}
+
+pub const OBJECTIVES: [&str; 4] = ["Distance", "Paint", "Variety", "Stillness"]; // All of them are maximised.
+const AXIS_PAIRS: [(usize, usize); 6] = [(0, 1), (0, 2), (0, 3), (1, 2), (1, 3), (2, 3)];
+
+pub fn dominates( // At least as good on everything, and better on something.
+    a: &[f32],
+    b: &[f32],
+) -> bool {
+    a.iter().zip(b.iter()).all(|(x, y)| x >= y) && a.iter().zip(b.iter()).any(|(x, y)| x > y)
+}
+
+pub fn pareto_fronts( // Non-dominated sorting. 0 is the Pareto front, 1 what would be the front without it, and so on.
+    objectives: &[Vec<f32>]
+) -> Vec<usize> {
+    let mut fronts = vec![0; objectives.len()];
+    let mut remaining: Vec<usize> = (0..objectives.len()).collect();
+    let mut level = 0;
+    while !remaining.is_empty() {
+        let front: Vec<usize> = remaining.iter().copied()
+            .filter(|i| !remaining.iter().any(|j| dominates(&objectives[*j], &objectives[*i])))
+            .collect();
+        for i in front.iter() {
+            fronts[*i] = level;
+        }
+        remaining.retain(|i| !front.contains(i));
+        level += 1;
+    }
+    fronts
+}
+
+pub fn crowding_distances( // How much room each one has on its own front. The extremes get infinity, so they are kept.
+    objectives: &[Vec<f32>],
+    fronts: &[usize],
+) -> Vec<f32> {
+    let mut crowding = vec![0.; objectives.len()];
+    let levels = fronts.iter().max().map_or(0, |m| m + 1);
+    let dimensions = objectives.first().map_or(0, Vec::len);
+    let value = |i: usize, o: usize| objectives[i][o];
+    for level in 0..levels {
+        let mut members: Vec<usize> = (0..objectives.len()).filter(|i| fronts[*i] == level).collect();
+        for o in 0..dimensions {
+            members.sort_by(|a, b| value(*a, o).total_cmp(&value(*b, o)));
+            let (first, last) = (members[0], members[members.len() - 1]);
+            crowding[first] = f32::INFINITY;
+            crowding[last] = f32::INFINITY;
+            let span = value(last, o) - value(first, o);
+            if span <= 0. {
+                continue;
+            }
+            for neighbours in members.windows(3) {
+                crowding[neighbours[1]] += (value(neighbours[2], o) - value(neighbours[0], o)) / span;
+            }
+        }
+    }
+    crowding
+}
+
+pub fn crowded_tournament( // NSGA-II's binary tournament: the better front wins, then the less crowded.
+    a: usize,
+    b: usize,
+    fronts: &[usize],
+    crowding: &[f32],
+) -> usize {
+    match fronts[a].cmp(&fronts[b]) {
+        std::cmp::Ordering::Less => a,
+        std::cmp::Ordering::Greater => b,
+        std::cmp::Ordering::Equal => if crowding[a] >= crowding[b] { a } else { b },
+    }
+}
+
+pub fn survivors( // NSGA-II's environmental selection: whole fronts while they fit, then the least crowded of the next one.
+    objectives: &[Vec<f32>],
+    size: usize,
+) -> Vec<usize> {
+    let fronts = pareto_fronts(objectives);
+    let crowding = crowding_distances(objectives, &fronts);
+    let mut ranking: Vec<usize> = (0..objectives.len()).collect();
+    ranking.sort_by(|a, b| fronts[*a].cmp(&fronts[*b]).then(crowding[*b].total_cmp(&crowding[*a])));
+    ranking.truncate(size);
+    ranking
+}
+
+#[derive(Clone)]
+pub struct EliteMember {
+    pub brain: Net,
+    pub objectives: Vec<f32>,
+    pub population: Species, // Prey and Hunters, or Red and Blue, each keep their own.
+    pub front: usize, // Among the elite of its population.
+    pub crowding: f32,
+}
+
+#[derive(Resource, Default, Clone)]
+pub struct ParetoElite { // NSGA-II's parent population: the best of every parent and child so far, so a good one is never lost to a bad draw.
+    pub members: Vec<EliteMember>,
+}
+
+impl ParetoElite {
+    pub fn renew( // The elite and the generation just played compete, as many as each population has slots stay on.
+        &mut self,
+        newcomers: Vec<EliteMember>,
+        sizes: &[(Species, usize)],
+    ){
+        let mut pool = std::mem::take(&mut self.members);
+        pool.extend(newcomers);
+        for (population, size) in sizes.iter(){
+            let candidates: Vec<usize> = (0..pool.len()).filter(|i| pool[*i].population == *population).collect();
+            let objectives: Vec<Vec<f32>> = candidates.iter().map(|i| pool[*i].objectives.clone()).collect();
+            let kept: Vec<usize> = survivors(&objectives, *size).into_iter().map(|k| candidates[k]).collect();
+            let kept_objectives: Vec<Vec<f32>> = kept.iter().map(|i| pool[*i].objectives.clone()).collect();
+            let fronts = pareto_fronts(&kept_objectives); // Again, among the survivors only, for the tournaments.
+            let crowding = crowding_distances(&kept_objectives, &fronts);
+            for (k, i) in kept.into_iter().enumerate(){
+                self.members.push(EliteMember{front: fronts[k], crowding: crowding[k], ..pool[i].clone()});
+            }
+        }
+    }
+    pub fn pick( // A crowded tournament between two of the elite of that population.
+        &self,
+        population: Species,
+        rng: &mut impl Rng,
+    ) -> Option<&Net> {
+        let members: Vec<&EliteMember> = self.members.iter().filter(|m| m.population == population).collect();
+        if members.is_empty() {
+            return None;
+        }
+        let fronts: Vec<usize> = members.iter().map(|m| m.front).collect();
+        let crowding: Vec<f32> = members.iter().map(|m| m.crowding).collect();
+        let (a, b) = (rng.gen_range(0..members.len()), rng.gen_range(0..members.len()));
+        Some(&members[crowded_tournament(a, b, &fronts, &crowding)].brain)
+    }
+}
+
+#[derive(Resource)]
+pub struct ParetoView { // Scatter plot of the shipped generation, left of the arena.
+    pub axes: Option<(usize, usize)>, // Indices in OBJECTIVES.
+}
+
+#[derive(Component)]
+pub struct ParetoLabel;
+
+fn draw_pareto_label(
+    mut commands: Commands,
+){
+    commands.spawn((TextBundle::from_section(
+        "",
+        TextStyle {
+            font_size: 16.,
+            color: Color::WHITE,
+            ..default()
+        },
+    ).with_style(Style {
+        position_type: PositionType::Absolute,
+        bottom: Val::Px(32.),
+        left: Val::Px(4.),
+        ..default()
+    }), ParetoLabel));
+}
+
+fn cycle_pareto_view( // N goes through every pair of objectives, then hides the plot.
+    keys: Res<Input<KeyCode>>,
+    mut view: ResMut<ParetoView>,
+    mut label: Query<&mut Text, With<ParetoLabel>>,
+){
+    if keys.just_pressed(KeyCode::N) {
+        view.axes = match view.axes.and_then(|a| AXIS_PAIRS.iter().position(|p| *p == a)) {
+            None => Some(AXIS_PAIRS[0]),
+            Some(i) => AXIS_PAIRS.get(i + 1).copied(),
+        };
+    }
+    if !view.is_changed() {
+        return;
+    }
+    for mut text in label.iter_mut(){
+        text.sections[0].value = match view.axes {
+            Some((x, y)) => format!("Pareto: {} (across) vs {} (up), green is the front", OBJECTIVES[x], OBJECTIVES[y]),
+            None => String::new(),
+        };
+    }
+}
+
+fn draw_pareto_front(
+    view: Res<ParetoView>,
+    inspected: Res<Inspected>,
+    map: Res<Map>,
+    actors: Query<(Entity, &FinishedTrace), Without<Compared>>,
+    mut gizmos: Gizmos,
+){
+    let Some((x_axis, y_axis)) = view.axes else { return };
+    let points: Vec<(Entity, Vec2, usize)> = actors.iter()
+        .filter_map(|(entity, trace)| {
+            let (x, y) = (trace.objectives.get(x_axis)?, trace.objectives.get(y_axis)?);
+            Some((entity, Vec2::new(*x, *y), trace.front?))
+        })
+        .collect();
+    let size = map.height as f32 * TILE_SIZE;
+    let origin = Vec2::new(-size - 3. * TILE_SIZE, 0.);
+    gizmos.line_2d(origin, origin + Vec2::new(size, 0.), Color::GRAY);
+    gizmos.line_2d(origin, origin + Vec2::new(0., size), Color::GRAY);
+    if points.is_empty() {
+        return;
+    }
+    let most = points.iter().fold(Vec2::splat(f32::EPSILON), |m, (_, p, _)| m.max(*p));
+    let place = |p: Vec2| origin + p / most * size;
+    let worst = points.iter().map(|(_, _, f)| *f).max().unwrap_or(0).max(1);
+    for (entity, point, front) in points.iter(){
+        let colour = match front {
+            0 => Color::rgb(0.3, 1., 0.3),
+            f => Color::rgba(0.8, 0.8, 0.8, 1. - 0.7 * *f as f32 / worst as f32),
+        };
+        gizmos.circle_2d(place(*point), 3., colour);
+        if inspected.actor == Some(*entity) {
+            gizmos.circle_2d(place(*point), 6., Color::rgb(1., 0.9, 0.2));
+        }
+    }
+    let mut front: Vec<Vec2> = points.iter().filter(|(_, _, f)| *f == 0).map(|(_, p, _)| *p).collect();
+    front.sort_by(|a, b| a.x.total_cmp(&b.x));
+    for pair in front.windows(2){
+        gizmos.line_2d(place(pair[0]), place(pair[1]), Color::rgba(0.3, 1., 0.3, 0.5));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dominance_needs_something_better() {
+        assert!(dominates(&[2., 1.], &[1., 1.]));
+        assert!(!dominates(&[1., 1.], &[1., 1.]));
+        assert!(!dominates(&[2., 0.], &[1., 1.]));
+    }
+
+    #[test]
+    fn fronts_peel_off_in_layers() {
+        let objectives = vec![vec![3., 1.], vec![1., 3.], vec![2., 2.], vec![1., 1.], vec![0., 0.], vec![2., 2.]];
+        assert_eq!(pareto_fronts(&objectives), vec![0, 0, 0, 1, 2, 0]);
+        assert!(pareto_fronts(&[]).is_empty());
+    }
+
+    #[test]
+    fn crowding_favours_the_extremes_and_the_lonely() {
+        let objectives = vec![vec![0., 4.], vec![1., 3.], vec![3., 1.], vec![4., 0.], vec![0., 0.]];
+        let fronts = pareto_fronts(&objectives);
+        let crowding = crowding_distances(&objectives, &fronts);
+        assert_eq!(crowding[0], f32::INFINITY);
+        assert_eq!(crowding[3], f32::INFINITY);
+        assert_eq!(crowding[1], 1.5); // (3 - 0) / 4 on both objectives.
+        assert_eq!(crowding[2], 1.5);
+        assert_eq!(crowding[4], f32::INFINITY); // Alone on its front.
+    }
+
+    #[test]
+    fn survivors_fill_by_front_then_crowding() {
+        let objectives = vec![vec![0., 4.], vec![2., 2.], vec![4., 0.], vec![1.9, 1.9], vec![0., 3.5], vec![3.5, 0.], vec![0., 0.]];
+        let mut kept = survivors(&objectives, 5);
+        kept.sort();
+        assert_eq!(kept, vec![0, 1, 2, 4, 5]); // The whole front, then the extremes of the second.
+        assert_eq!(survivors(&objectives, 10).len(), objectives.len());
+    }
+
+    #[test]
+    fn the_elite_keeps_the_best_of_parents_and_children() {
+        let mut rng = rand::thread_rng();
+        let mut member = |objectives: Vec<f32>, population| EliteMember{brain: Net::new(vec![1, 1], &mut rng), objectives, population, front: 0, crowding: 0.};
+        let mut elite = ParetoElite{members: vec![member(vec![5., 5.], Species::Psychic), member(vec![0., 0.], Species::Psychic)]};
+        elite.renew(vec![member(vec![1., 1.], Species::Psychic), member(vec![2., 0.], Species::Psychic), member(vec![9., 9.], Species::Hunter)], &[(Species::Psychic, 2), (Species::Hunter, 1)]);
+        let kept: Vec<(Vec<f32>, Species)> = elite.members.iter().map(|m| (m.objectives.clone(), m.population)).collect();
+        assert_eq!(kept.len(), 3);
+        assert!(kept.contains(&(vec![5., 5.], Species::Psychic))); // An old parent still beats this generation.
+        assert!(kept.contains(&(vec![9., 9.], Species::Hunter)));
+        assert!(!kept.contains(&(vec![0., 0.], Species::Psychic)));
+        assert!(elite.pick(Species::Hunter, &mut rng).is_some());
+        assert!(elite.pick(Species::Red, &mut rng).is_none());
+    }
+}
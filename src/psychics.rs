@@ -4,19 +4,20 @@ use bevy::prelude::*;
 use bevy::sprite::Anchor::BottomLeft;
 use bevy_tweening::lens::TransformPositionLens;
 use bevy_tweening::{Animator, Tween, EaseFunction};
+use rand::Rng;
 
 use crate::axiom::{Axiom, AxiomKit};
 use crate::map::{Map, Species, build_map};
 use crate::SpriteSheetHandle;
 use crate::nn::Net;
-use crate::simulation::{PLAY_AREA_HEIGHT, PLAY_AREA_WIDTH, MAX_TURN_NUMBER};
-use crate::theatre::TILE_SIZE;
+use crate::scripts::Script;
+use crate::simulation::{MAX_TURN_NUMBER, TrainingRng, sense_count};
+use crate::theatre::{TILE_SIZE, get_texture_id};
 
 pub struct PsychicPlugin;
 
 impl Plugin for PsychicPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(PsychicSettings{number_at_start: 16});
         app.add_systems(Startup, distribute_psychics);
         //app.register_type::<Soul>();
         //app.register_type::<Position>();
@@ -37,7 +38,8 @@ pub struct HylicBundle {
     position: Position,
     trace: Trace,
     name: Name,
-    species: Species
+    species: Species,
+    script: Script,
 }
 
 #[derive(Bundle)]
@@ -79,7 +81,7 @@ impl TheatreBundle { // Creatures displayed on screen.
                 ..default()
             },
             animation: Animator::new(tween),
-            finished_trace: FinishedTrace{positions: Vec::new(), identity: Vec::new()},
+            finished_trace: FinishedTrace::default(),
             name: Name::new("TheatreDisplay"),
             species: Species::Wall
         }
@@ -104,6 +106,14 @@ impl TheatreBundle { // Creatures displayed on screen.
         self.species = species;
         self
     }
+    pub fn with_trace(mut self, trace: FinishedTrace) -> Self {
+        self.finished_trace = trace;
+        self
+    }
+    pub fn with_source(mut self, source: Entity) -> Self { // The simulated creature it will display.
+        self.finished_trace.source = Some(source);
+        self
+    }
 }
 
 impl PsychicBundle { // Creatures simulated in the genetic process.
@@ -111,25 +121,19 @@ impl PsychicBundle { // Creatures simulated in the genetic process.
     pub fn new() -> Self {
         Self{
             soul: Soul {                 
-                nn: Net::new(vec![
-                    4_usize,
-                    15,
-                    5,
-                ]),
+                nn: Net::default(), // Filled by with_axiom_kits or with_brain.
                 senses_input: Vec::new(),
                 decision_outputs: Vec::new(), 
                 action_choices: Vec::new(),
                 actions_chosen: Vec::new(),
-                fitness: 0.
-            },
-            position: Position { x: 0, y: 0, starting_position: (0, 0) },
-            trace: Trace {
-                positions: Vec::with_capacity(MAX_TURN_NUMBER),
-                shipped_positions: Vec::with_capacity(MAX_TURN_NUMBER), 
-                identity: Vec::with_capacity(MAX_TURN_NUMBER), 
-                shipped_identity: Vec::with_capacity(MAX_TURN_NUMBER),
-                original_species: Species::Wall
+                fitness: 0.,
+                novelty: 0.,
+                clade: None,
+                stand_in: false,
+                energy: 0.,
             },
+            position: Position { x: 0, y: 0, starting_position: (0, 0), benched: false },
+            trace: Trace::new(Species::Wall),
             name: Name::new("Psychic"),
             species: Species::Wall
         }
@@ -140,16 +144,16 @@ impl PsychicBundle { // Creatures simulated in the genetic process.
         self.position.starting_position = (x, y);
         self
     }
-    pub fn with_axiom_kits(mut self, kits: Vec<AxiomKit>) -> Self{
+    pub fn with_axiom_kits(mut self, kits: Vec<AxiomKit>, sense_count: usize, rng: &mut impl Rng) -> Self{
         for kit in kits{
             self.soul.action_choices.append(&mut kit.unpack());
         }
         self.soul.nn = Net::new(vec![
-            43_usize, // This will have to be adapted to the number of Senses.
+            sense_count,
             40,
             40,
             self.soul.action_choices.len(),
-        ]);
+        ], rng);
         self
     }
     pub fn with_species(mut self, species: Species) -> Self {
@@ -157,21 +161,26 @@ impl PsychicBundle { // Creatures simulated in the genetic process.
         self.trace.original_species = species;
         self
     }
+    pub fn with_brain(mut self, nn: Net, action_choices: Vec<Axiom>) -> Self { // A trained one, instead of a random one from with_axiom_kits.
+        self.soul.nn = nn;
+        self.soul.action_choices = action_choices;
+        self
+    }
+    pub fn with_first_turn(mut self) -> Self { // Same as for Hylics, when evolve_generation will not be the one placing it.
+        self.trace.positions.push((self.position.x, self.position.y));
+        self.trace.identity.push(self.species);
+        self
+    }
 }
 
 impl HylicBundle { // Creatures without a neural network, who present challenges for the Psychics.
     pub fn new() -> Self{
         Self{
-            position: Position { x: 0, y: 0, starting_position: (0, 0) },
-            trace: Trace {
-                positions: Vec::with_capacity(MAX_TURN_NUMBER),
-                shipped_positions: Vec::with_capacity(MAX_TURN_NUMBER), 
-                identity: Vec::with_capacity(MAX_TURN_NUMBER), 
-                shipped_identity: Vec::with_capacity(MAX_TURN_NUMBER),
-                original_species: Species::Wall
-            },
+            position: Position { x: 0, y: 0, starting_position: (0, 0), benched: false },
+            trace: Trace::new(Species::Wall),
             name: Name::new("Hylic"),
-            species: Species::Wall
+            species: Species::Wall,
+            script: Script::Still,
         }
     }
     pub fn with_position(mut self, x: u32, y: u32) -> Self { // Absolutely immaculate!
@@ -185,6 +194,15 @@ impl HylicBundle { // Creatures without a neural network, who present challenges
         self.trace.original_species = species;
         self
     }
+    pub fn with_script(mut self, script: Script) -> Self {
+        self.script = script;
+        self
+    }
+    pub fn with_first_turn(mut self) -> Self { // For Hylics joining mid-training, evolve_generation already filled everyone else's first turn.
+        self.trace.positions.push((self.position.x, self.position.y));
+        self.trace.identity.push(self.species);
+        self
+    }
 }
 
 #[derive(Component)]
@@ -199,18 +217,18 @@ pub struct Soul{
     pub action_choices: Vec<Axiom>,
     pub actions_chosen: Vec<(i32, i32)>,
     pub fitness: f32,
-}
-
-#[derive(Resource)]
-pub struct PsychicSettings {
-    pub number_at_start: u32,
+    pub novelty: f32, // How unlike the others and the archive its last generation was. Only scored when it counts.
+    pub clade: Option<usize>, // Which species its brain was sorted into, see speciation::Clade. None when not speciating.
+    pub stand_in: bool, // Plays an archived champion of its population this generation, see coevolution.
+    pub energy: f32, // Left this generation. Only spent when the map has energy rules.
 }
 
 #[derive(Component, Default, Reflect)]
 pub struct Position{
     pub x: u32,
     pub y: u32,
-    pub starting_position: (u32, u32)
+    pub starting_position: (u32, u32),
+    pub benched: bool, // No room for it on this generation's map, so it sits out and does nothing.
 }
 
 #[derive(Component)]
@@ -219,47 +237,110 @@ pub struct Trace{
     pub shipped_positions: Vec<(u32, u32)>,
     pub identity: Vec<Species>,
     pub shipped_identity: Vec<Species>,
+    pub actions: Vec<Axiom>,
+    pub shipped_actions: Vec<Axiom>,
+    pub shipped_fitness: f32,
+    pub shipped_objectives: Vec<f32>, // Only for Psychics, see pareto::OBJECTIVES.
+    pub shipped_front: Option<usize>, // Which Pareto front it was on, 0 is the non-dominated one.
+    pub records: Vec<TurnRecord>, // Only filled for Psychics, and only if SimulationSettings.record_decisions is on.
+    pub shipped_records: Vec<TurnRecord>,
+    pub shipped_brain: Option<Net>, // The Soul gets replaced by its offspring right after shipping, so keep a copy.
     pub original_species: Species,
 }
 
-#[derive(Component)]
+impl Trace {
+    pub fn new(species: Species) -> Self {
+        Self {
+            positions: Vec::with_capacity(MAX_TURN_NUMBER),
+            shipped_positions: Vec::with_capacity(MAX_TURN_NUMBER), 
+            identity: Vec::with_capacity(MAX_TURN_NUMBER), 
+            shipped_identity: Vec::with_capacity(MAX_TURN_NUMBER),
+            actions: Vec::with_capacity(MAX_TURN_NUMBER),
+            shipped_actions: Vec::with_capacity(MAX_TURN_NUMBER),
+            shipped_fitness: 0.,
+            shipped_objectives: Vec::new(),
+            shipped_front: None,
+            records: Vec::new(),
+            shipped_records: Vec::new(),
+            shipped_brain: None,
+            original_species: species
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct TurnRecord { // Why a Psychic did what it did on a given turn.
+    pub senses: Vec<f64>,
+    pub outputs: Vec<f64>,
+    pub action: Axiom,
+    pub fitness_delta: f32,
+}
+
+#[derive(Component, Default)]
 pub struct FinishedTrace{
     pub positions: Vec<(u32, u32)>,
     pub identity: Vec<Species>,
+    pub source: Option<Entity>, // The simulated creature this was shipped from.
+    pub fitness: f32,
+    pub rank: Option<usize>, // Among the Psychics of its generation, 0 is the fittest.
+    pub objectives: Vec<f32>, // Empty in replays.
+    pub front: Option<usize>,
+    pub actions: Vec<Axiom>,
+    pub records: Vec<TurnRecord>,
+    pub brain: Option<Net>,
+    pub action_choices: Vec<Axiom>, // What each of the brain's outputs stands for.
+}
+
+impl FinishedTrace {
+    pub fn is_psychic(&self) -> bool {
+        self.identity.first().is_some_and(|s| s.is_psychic())
+    }
 }
 
 fn distribute_psychics(
     mut commands: Commands,
-    tex_handle: Res<SpriteSheetHandle>,
+    tex_handle: Option<Res<SpriteSheetHandle>>, // Missing when running headless, then there is no theatre to fill.
     mut map: ResMut<Map>,
+    mut rng: ResMut<TrainingRng>,
 ){
 
-    (map.tiles, map.catalogue, map.locations, map.axiom_map) = build_map(map.population.clone()); // TODO: Make a set of possible maps and starting locations, then ship that and stop generating stuff when we're busy enough training the NN.
-    for y in 0..PLAY_AREA_HEIGHT {
-        for x in 0..PLAY_AREA_WIDTH {
+    (map.tiles, map.catalogue, map.locations, map.axiom_map) = build_map(map.population.clone(), map.width, map.height, map.topology, &mut rng.0);
+    map.starting_tiles = map.tiles.clone(); // TODO: Make a set of possible maps and starting locations, then ship that and stop generating stuff when we're busy enough training the NN.
+    for y in 0..map.height {
+        for x in 0..map.width {
             let idx = map.xy_idx(x, y);
             let tile = &map.tiles[idx];
             match tile {
                 Species::Wall => {
-                    let wall_t = TheatreBundle::new(&tex_handle).with_sprite(3).with_position(x, y).with_species(Species::Wall);
-                    let wall = HylicBundle::new().with_position(x, y).with_species(Species::Wall);
-                    commands.spawn(wall_t);
-                    commands.spawn(wall);
+                    let wall = HylicBundle::new().with_position(x, y).with_species(Species::Wall).with_script(map.script_for(Species::Wall));
+                    let source = commands.spawn(wall).id();
+                    if let Some(tex_handle) = &tex_handle {
+                        commands.spawn(TheatreBundle::new(tex_handle).with_sprite(3).with_position(x, y).with_species(Species::Wall).with_source(source));
+                    }
                 },
-                Species::Psychic => {
+                Species::Psychic | Species::Hunter | Species::Red | Species::Blue => {
+                    let kit = match (*tile, map.rival_senses) {
+                        (Species::Hunter, _) => AxiomKit::HuntKit,
+                        (Species::Red | Species::Blue, _) => AxiomKit::TeamKit(*tile),
+                        (_, true) => AxiomKit::Motion, // Prey only need to run.
+                        (_, false) => AxiomKit::PaintKit,
+                    };
+                    let species = *tile;
                     let psy = PsychicBundle::new()
                         .with_position(x, y)
-                        .with_axiom_kits(vec![AxiomKit::PaintKit])
-                        .with_species(Species::Psychic);
-                    let theatre = TheatreBundle::new(&tex_handle).with_position(x, y).with_species(Species::Psychic);
-                    commands.spawn(psy);
-                    commands.spawn(theatre);
+                        .with_axiom_kits(vec![kit], sense_count(&map), &mut rng.0)
+                        .with_species(species);
+                    let source = commands.spawn(psy).id();
+                    if let Some(tex_handle) = &tex_handle {
+                        commands.spawn(TheatreBundle::new(tex_handle).with_sprite(get_texture_id(species)).with_position(x, y).with_species(species).with_source(source));
+                    }
                 },
                 Species::Beacon => {
-                    let mark = HylicBundle::new().with_position(x, y).with_species(Species::Beacon);
-                    let x_spot = TheatreBundle::new(&tex_handle).with_sprite(1).with_position(x, y).with_species(Species::Beacon); // sprite and species should probably be merged
-                    commands.spawn(mark);
-                    commands.spawn(x_spot);
+                    let mark = HylicBundle::new().with_position(x, y).with_species(Species::Beacon).with_script(map.script_for(Species::Beacon));
+                    let source = commands.spawn(mark).id();
+                    if let Some(tex_handle) = &tex_handle {
+                        commands.spawn(TheatreBundle::new(tex_handle).with_sprite(1).with_position(x, y).with_species(Species::Beacon).with_source(source)); // sprite and species should probably be merged
+                    }
                 }
                 _ => ()
             }
@@ -0,0 +1,424 @@
+use std::{fs, io::{self, Write, BufWriter}, path::{Path, PathBuf}, str::FromStr};
+
+use bevy::prelude::*;
+
+use crate::{axiom::Axiom, config::Config, export::{load_spritesheet, render_frames, write_gif}, map::{Map, Species}, psychics::{Soul, Trace, TurnRecord}, simulation::{GenerationFinished, evolve_generation}};
+
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        let config = app.world.get_resource::<Config>().cloned().unwrap_or_default();
+        app.insert_resource(ReplayRecorder{
+            policy: config.record_policy,
+            directory: config.replay_directory,
+            best_fitness: 0.,
+            export_gif: config.export_recorded,
+            frame_delay: config.frame_delay,
+        });
+        app.add_systems(Update, record_replay.after(evolve_generation));
+    }
+}
+
+const MAGIC: &[u8; 4] = b"TGFP";
+const VERSION: u8 = 1;
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum RecordPolicy {
+    #[default]
+    Never,
+    Every(usize), // Every Nth generation.
+    Improving, // Only generations which beat the best fitness seen so far.
+}
+
+impl FromStr for RecordPolicy {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(RecordPolicy::Never),
+            "all" => Ok(RecordPolicy::Every(1)),
+            "improving" => Ok(RecordPolicy::Improving),
+            _ => match s.parse::<usize>() {
+                Ok(n) if n > 0 => Ok(RecordPolicy::Every(n)),
+                _ => Err(()),
+            }
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct ReplayRecorder {
+    pub policy: RecordPolicy,
+    pub directory: PathBuf,
+    pub best_fitness: f32,
+    pub export_gif: bool, // Write a GIF next to every saved replay.
+    pub frame_delay: u32,
+}
+
+pub struct Replay { // Everything needed to watch a generation again, long after it was simulated.
+    pub generation: usize,
+    pub width: u32,
+    pub height: u32,
+    pub tiles: Vec<Species>, // The map as it was on the first turn.
+    pub entities: Vec<ReplayEntity>,
+}
+
+pub struct ReplayEntity {
+    pub fitness: f32,
+    pub positions: Vec<(u32, u32)>,
+    pub identity: Vec<Species>,
+    pub actions: Vec<Axiom>,
+    pub action_choices: Vec<Axiom>,
+    pub records: Vec<TurnRecord>,
+}
+
+impl Replay {
+    pub fn file_name(generation: usize) -> String {
+        format!("generation_{generation:06}.tgfp")
+    }
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut out = BufWriter::new(fs::File::create(path)?);
+        out.write_all(MAGIC)?;
+        out.write_all(&[VERSION])?;
+        out.write_all(&(self.generation as u32).to_le_bytes())?;
+        out.write_all(&self.width.to_le_bytes())?;
+        out.write_all(&self.height.to_le_bytes())?;
+        let tiles: Vec<u8> = self.tiles.iter().map(|s| species_to_byte(*s)).collect();
+        out.write_all(&tiles)?;
+        out.write_all(&(self.entities.len() as u32).to_le_bytes())?;
+        for entity in self.entities.iter() {
+            out.write_all(&entity.fitness.to_le_bytes())?;
+            out.write_all(&(entity.positions.len() as u32).to_le_bytes())?;
+            for (i, (x, y)) in entity.positions.iter().enumerate() {
+                out.write_all(&(*x as u16).to_le_bytes())?;
+                out.write_all(&(*y as u16).to_le_bytes())?;
+                let species = entity.identity.get(i).copied().unwrap_or(Species::Nothing);
+                out.write_all(&[species_to_byte(species)])?;
+            }
+            out.write_all(&(entity.actions.len() as u32).to_le_bytes())?;
+            for action in entity.actions.iter() {
+                out.write_all(&axiom_to_bytes(*action))?;
+            }
+            out.write_all(&(entity.action_choices.len() as u32).to_le_bytes())?;
+            for choice in entity.action_choices.iter() {
+                out.write_all(&axiom_to_bytes(*choice))?;
+            }
+            out.write_all(&(entity.records.len() as u32).to_le_bytes())?;
+            for record in entity.records.iter() {
+                write_floats(&mut out, &record.senses)?;
+                write_floats(&mut out, &record.outputs)?;
+                out.write_all(&axiom_to_bytes(record.action))?;
+                out.write_all(&record.fitness_delta.to_le_bytes())?;
+            }
+        }
+        out.flush()
+    }
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        let mut reader = ByteReader{bytes: &bytes, cursor: 0};
+        if reader.take(4)? != MAGIC {
+            return Err(invalid("not a replay file"));
+        }
+        let version = reader.u8()?;
+        if version != VERSION {
+            return Err(invalid("unsupported replay version"));
+        }
+        let generation = reader.u32()? as usize;
+        let width = reader.u32()?;
+        let height = reader.u32()?;
+        let tiles = reader.take(tile_count(width, height)?)?.iter().map(|byte| byte_to_species(*byte)).collect::<io::Result<Vec<_>>>()?;
+        let entity_count = reader.u32()?;
+        let mut entities = Vec::with_capacity(reader.capacity(entity_count as usize, 20));
+        for _i in 0..entity_count {
+            let fitness = reader.f32()?;
+            let turns = reader.u32()?;
+            let mut positions = Vec::with_capacity(reader.capacity(turns as usize, 5));
+            let mut identity = Vec::with_capacity(reader.capacity(turns as usize, 5));
+            for _t in 0..turns {
+                positions.push((reader.u16()? as u32, reader.u16()? as u32));
+                identity.push(byte_to_species(reader.u8()?)?);
+            }
+            let action_count = reader.u32()?;
+            let mut actions = Vec::with_capacity(reader.capacity(action_count as usize, 3));
+            for _a in 0..action_count {
+                actions.push(read_axiom(&mut reader)?);
+            }
+            let mut action_choices = Vec::new();
+            for _c in 0..reader.u32()? {
+                action_choices.push(read_axiom(&mut reader)?);
+            }
+            let mut records = Vec::new();
+            for _r in 0..reader.u32()? {
+                let senses = reader.floats()?;
+                let outputs = reader.floats()?;
+                let action = read_axiom(&mut reader)?;
+                let fitness_delta = reader.f32()?;
+                records.push(TurnRecord{senses, outputs, action, fitness_delta});
+            }
+            entities.push(ReplayEntity{fitness, positions, identity, actions, action_choices, records});
+        }
+        Ok(Self{generation, width, height, tiles, entities})
+    }
+    pub fn turn_count(&self) -> usize {
+        self.entities.iter().map(|e| e.positions.len()).max().unwrap_or(0)
+    }
+}
+
+pub fn list_replays(directory: &Path) -> Vec<PathBuf> { // Sorted, so they come out in generation order.
+    let Ok(entries) = fs::read_dir(directory) else { return Vec::new() };
+    let mut output: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|ext| ext == "tgfp"))
+        .collect();
+    output.sort();
+    output
+}
+
+fn record_replay(
+    mut finished: EventReader<GenerationFinished>,
+    traces: Query<(&Trace, Option<&Soul>)>,
+    map: Res<Map>,
+    mut recorder: ResMut<ReplayRecorder>,
+){
+    for event in finished.read(){
+        let wanted = match recorder.policy {
+            RecordPolicy::Never => false,
+            RecordPolicy::Every(n) => event.generation % n == 0,
+            RecordPolicy::Improving => event.best_fitness > recorder.best_fitness,
+        };
+        recorder.best_fitness = recorder.best_fitness.max(event.best_fitness);
+        if !wanted {
+            continue;
+        }
+        let mut entities = Vec::new();
+        for (trace, soul) in traces.iter(){
+            entities.push(ReplayEntity{
+                fitness: trace.shipped_fitness,
+                positions: trace.shipped_positions.clone(),
+                identity: trace.shipped_identity.clone(),
+                actions: trace.shipped_actions.clone(),
+                action_choices: soul.map(|s| s.action_choices.clone()).unwrap_or_default(),
+                records: trace.shipped_records.clone(),
+            });
+        }
+        let replay = Replay{
+            generation: event.generation,
+            width: map.shipped_width,
+            height: map.shipped_height,
+            tiles: map.shipped_tiles.clone(),
+            entities,
+        };
+        let path = recorder.directory.join(Replay::file_name(event.generation));
+        if let Err(e) = replay.save(&path) {
+            warn!("Could not save the replay of generation {}: {e}", event.generation);
+        }
+        if recorder.export_gif && replay.turn_count() > 0 {
+            let gif = load_spritesheet().and_then(|sheet| write_gif(render_frames(&replay, &sheet), &path.with_extension("gif"), recorder.frame_delay));
+            if let Err(e) = gif {
+                warn!("Could not export the replay of generation {} as a GIF: {e}", event.generation);
+            }
+        }
+    }
+}
+
+pub fn tile_count( // Straight from a file header, so it could be anything.
+    width: u32,
+    height: u32,
+) -> io::Result<usize> {
+    (width as usize).checked_mul(height as usize).ok_or_else(|| invalid("arena too large"))
+}
+
+pub struct ByteReader<'a> { // Also reads checkpoints.
+    pub bytes: &'a [u8],
+    pub cursor: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if n > self.bytes.len() - self.cursor {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+        }
+        let slice = &self.bytes[self.cursor..self.cursor + n];
+        self.cursor += n;
+        Ok(slice)
+    }
+    pub fn capacity(&self, count: usize, item_size: usize) -> usize { // A count read from the file can't hold more items than there are bytes left.
+        count.min((self.bytes.len() - self.cursor) / item_size)
+    }
+    pub fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+    pub fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+    pub fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+    pub fn i32(&mut self) -> io::Result<i32> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+    pub fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+    pub fn u128(&mut self) -> io::Result<u128> {
+        Ok(u128::from_le_bytes(self.take(16)?.try_into().unwrap()))
+    }
+    pub fn f32(&mut self) -> io::Result<f32> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+    pub fn f64(&mut self) -> io::Result<f64> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+    pub fn floats(&mut self) -> io::Result<Vec<f64>> {
+        let count = self.u16()?;
+        let mut output = Vec::with_capacity(self.capacity(count as usize, 4));
+        for _i in 0..count {
+            output.push(self.f32()? as f64);
+        }
+        Ok(output)
+    }
+}
+
+fn write_floats( // Stored as f32, the precision of f64 is wasted on a replay.
+    out: &mut impl Write,
+    values: &[f64],
+) -> io::Result<()> {
+    out.write_all(&(values.len() as u16).to_le_bytes())?;
+    for v in values {
+        out.write_all(&(*v as f32).to_le_bytes())?;
+    }
+    Ok(())
+}
+
+pub fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub fn species_to_byte(species: Species) -> u8 {
+    match species {
+        Species::Wall => 0,
+        Species::Nothing => 1,
+        Species::Psychic => 2,
+        Species::Beacon => 3,
+        Species::TermiPainted => 4,
+        Species::Hunter => 5,
+        Species::Caught => 6,
+        Species::Red => 7,
+        Species::Blue => 8,
+        Species::RedPainted => 9,
+        Species::BluePainted => 10,
+    }
+}
+
+pub fn byte_to_species(byte: u8) -> io::Result<Species> {
+    match byte {
+        0 => Ok(Species::Wall),
+        1 => Ok(Species::Nothing),
+        2 => Ok(Species::Psychic),
+        3 => Ok(Species::Beacon),
+        4 => Ok(Species::TermiPainted),
+        5 => Ok(Species::Hunter),
+        6 => Ok(Species::Caught),
+        7 => Ok(Species::Red),
+        8 => Ok(Species::Blue),
+        9 => Ok(Species::RedPainted),
+        10 => Ok(Species::BluePainted),
+        _ => Err(invalid("unknown species")),
+    }
+}
+
+pub fn axiom_to_bytes(axiom: Axiom) -> Vec<u8> { // Three bytes, five for a teleport. Its target is stored like the positions.
+    match axiom {
+        Axiom::Move { dx, dy } => vec![0, dx as i8 as u8, dy as i8 as u8],
+        Axiom::PaintAdjacent { color } => vec![1, species_to_byte(color), 0],
+        Axiom::SpeciesTransform { new_species } => vec![2, species_to_byte(new_species), 0],
+        Axiom::Void => vec![3, 0, 0],
+        Axiom::Tag => vec![4, 0, 0],
+        Axiom::Teleport { x, y } => [[5].as_slice(), &(x as u16).to_le_bytes(), &(y as u16).to_le_bytes()].concat(),
+    }
+}
+
+pub fn read_axiom(reader: &mut ByteReader) -> io::Result<Axiom> {
+    let bytes = reader.take(3)?;
+    match bytes[0] {
+        0 => Ok(Axiom::Move { dx: bytes[1] as i8 as i32, dy: bytes[2] as i8 as i32 }),
+        1 => Ok(Axiom::PaintAdjacent { color: byte_to_species(bytes[1])? }),
+        2 => Ok(Axiom::SpeciesTransform { new_species: byte_to_species(bytes[1])? }),
+        3 => Ok(Axiom::Void),
+        4 => Ok(Axiom::Tag),
+        5 => {
+            let y = reader.take(2)?;
+            Ok(Axiom::Teleport { x: u16::from_le_bytes([bytes[1], bytes[2]]) as u32, y: u16::from_le_bytes([y[0], y[1]]) as u32 })
+        },
+        _ => Err(invalid("unknown axiom")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let moves = vec![Axiom::Move{dx: 1, dy: 0}, Axiom::PaintAdjacent{color: Species::TermiPainted}];
+        let replay = Replay{generation: 7, width: 3, height: 2, tiles: vec![Species::Wall, Species::Nothing, Species::Psychic, Species::Beacon, Species::Nothing, Species::Wall], entities: vec![
+            ReplayEntity{fitness: 12.5, positions: vec![(2, 0), (1, 0)], identity: vec![Species::Psychic, Species::Psychic], actions: vec![moves[0]], action_choices: moves.clone(), records: vec![
+                TurnRecord{senses: vec![0.5, 1.], outputs: vec![0.25, 0.75], action: moves[0], fitness_delta: -1.},
+            ]},
+            ReplayEntity{fitness: 0., positions: vec![(0, 1), (1, 1)], identity: vec![Species::Beacon, Species::Beacon], actions: vec![Axiom::Teleport{x: 1, y: 1}], action_choices: Vec::new(), records: Vec::new()},
+        ]};
+        let path = std::env::temp_dir().join(format!("tgfp_replay_test_{}.tgfp", std::process::id()));
+        replay.save(&path).unwrap();
+        let loaded = Replay::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!((loaded.generation, loaded.width, loaded.height), (7, 3, 2));
+        assert_eq!(loaded.tiles, replay.tiles);
+        assert_eq!(loaded.entities.len(), 2);
+        for (a, b) in loaded.entities.iter().zip(replay.entities.iter()){
+            assert_eq!(a.fitness, b.fitness);
+            assert_eq!(a.positions, b.positions);
+            assert_eq!(a.identity, b.identity);
+            assert_eq!(a.actions, b.actions);
+            assert_eq!(a.action_choices, b.action_choices);
+            assert_eq!(a.records.len(), b.records.len());
+            for (r, s) in a.records.iter().zip(b.records.iter()){
+                assert_eq!((&r.senses, &r.outputs, r.action, r.fitness_delta), (&s.senses, &s.outputs, s.action, s.fitness_delta));
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_a_corrupt_header() {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        bytes.extend(0u32.to_le_bytes());
+        bytes.extend(u32::MAX.to_le_bytes()); // Far more tiles than there are bytes.
+        bytes.extend(u32::MAX.to_le_bytes());
+        let path = std::env::temp_dir().join(format!("tgfp_corrupt_test_{}.tgfp", std::process::id()));
+        fs::write(&path, bytes).unwrap();
+        let loaded = Replay::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(loaded.is_err());
+    }
+
+    #[test]
+    fn counts_past_the_end_of_the_file_fail_without_allocating() {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        bytes.extend(0u32.to_le_bytes());
+        bytes.extend(1u32.to_le_bytes());
+        bytes.extend(1u32.to_le_bytes());
+        bytes.push(species_to_byte(Species::Nothing));
+        bytes.extend(u32::MAX.to_le_bytes()); // Entities.
+        bytes.extend(0f32.to_le_bytes());
+        bytes.extend(u32::MAX.to_le_bytes()); // Turns.
+        let path = std::env::temp_dir().join(format!("tgfp_counts_test_{}.tgfp", std::process::id()));
+        fs::write(&path, bytes).unwrap();
+        let loaded = Replay::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.err().map(|e| e.kind()), Some(io::ErrorKind::UnexpectedEof));
+    }
+}
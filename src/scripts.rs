@@ -0,0 +1,122 @@
+use std::str::FromStr;
+
+use bevy::prelude::*;
+use rand::{Rng, seq::IteratorRandom};
+
+use crate::{axiom::Axiom, map::{Map, Species, Topology}};
+
+const FLEE_RANGE: u32 = 5; // Psychics further away than this are not worth running from.
+const STEPS: [(i32, i32); 5] = [(0, 1), (0, -1), (-1, 0), (1, 0), (0, 0)];
+
+#[derive(Component, Clone, PartialEq, Debug, Default)]
+pub enum Script { // What a Hylic does on its own every turn. Decided from the turn number, so nothing to reset between generations.
+    #[default]
+    Still,
+    RandomWalk,
+    Patrol{route: Vec<(i32, i32)>}, // One step per turn, starting over once done. Blocked steps are skipped, not retried.
+    Flee, // Away from the nearest Psychic, if one comes close.
+    Teleport{every: usize}, // Jumps to a random empty tile every so many turns.
+}
+
+impl Script {
+    pub fn act(
+        &self,
+        pos: (u32, u32),
+        turn: usize,
+        map: &Map,
+        rng: &mut impl Rng,
+    ) -> Axiom {
+        let (dx, dy) = match self {
+            Script::Teleport { every } if turn > 0 && turn.is_multiple_of(*every) => {
+                let empty = map.tiles.iter().enumerate().filter(|(_, s)| **s == Species::Nothing).map(|(i, _)| i).choose(rng);
+                return empty.map_or(Axiom::Move { dx: 0, dy: 0 }, |i| Axiom::Teleport { x: i as u32 % map.width, y: i as u32 / map.width });
+            },
+            Script::Still => (0, 0),
+            Script::RandomWalk => STEPS[rng.gen_range(0..STEPS.len())],
+            Script::Patrol { route } => route.get(turn % route.len().max(1)).copied().unwrap_or((0, 0)),
+            Script::Flee => flee_step(pos, map),
+            Script::Teleport { .. } => (0, 0), // Waiting for its turn to jump.
+        };
+        Axiom::Move { dx, dy }
+    }
+}
+
+fn flee_step(
+    pos: (u32, u32),
+    map: &Map,
+) -> (i32, i32) {
+    let Some(threat) = map.tiles.iter().enumerate()
+        .filter(|(_, s)| s.is_psychic())
+        .map(|(i, _)| (i as u32 % map.width, i as u32 / map.width))
+        .min_by_key(|p| map.distance(*p, pos))
+        .filter(|p| map.distance(*p, pos) <= FLEE_RANGE) else { return (0, 0) };
+    STEPS.iter().copied()
+        .filter_map(|(dx, dy)| {
+            let x = pos.0 as i32 + dx;
+            let y = pos.1 as i32 + dy;
+            let (x, y) = match map.topology {
+                Topology::Toroidal => (x.rem_euclid(map.width as i32), y.rem_euclid(map.height as i32)),
+                Topology::Bounded => (x, y),
+            };
+            let inside = x >= 0 && y >= 0 && x < map.width as i32 && y < map.height as i32;
+            let free = (dx, dy) == (0, 0) || (inside && map.tiles[map.xy_idx(x as u32, y as u32)] == Species::Nothing);
+            free.then(|| ((dx, dy), map.distance((x as u32, y as u32), threat)))
+        })
+        .max_by_key(|(_, d)| *d)
+        .map_or((0, 0), |(step, _)| step)
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct ScriptRule { // One Hylic species of the map recipe, and what it does. e.g. "wall=random-walk" or "beacon=teleport:20"
+    pub species: Species,
+    pub script: Script,
+}
+
+impl FromStr for ScriptRule {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (species, script) = s.split_once('=').ok_or(())?;
+        let species = match species {
+            "wall" => Species::Wall,
+            "beacon" => Species::Beacon,
+            _ => return Err(()),
+        };
+        let script = match script.split_once(':') {
+            None if script == "still" => Script::Still,
+            None if script == "random-walk" => Script::RandomWalk,
+            None if script == "flee" => Script::Flee,
+            Some(("teleport", every)) => Script::Teleport { every: every.parse().ok().filter(|n| *n > 0).ok_or(())? },
+            Some(("patrol", route)) => Script::Patrol { route: route.chars().map(|c| match c { // Up and down as on screen.
+                'u' => Ok((0, 1)),
+                'd' => Ok((0, -1)),
+                'l' => Ok((-1, 0)),
+                'r' => Ok((1, 0)),
+                'w' => Ok((0, 0)), // Wait a turn.
+                _ => Err(()),
+            }).collect::<Result<Vec<_>, _>>()? },
+            _ => return Err(()),
+        };
+        Ok(ScriptRule { species, script })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_script() {
+        assert_eq!("wall=still".parse(), Ok(ScriptRule{species: Species::Wall, script: Script::Still}));
+        assert_eq!("wall=random-walk".parse(), Ok(ScriptRule{species: Species::Wall, script: Script::RandomWalk}));
+        assert_eq!("beacon=flee".parse(), Ok(ScriptRule{species: Species::Beacon, script: Script::Flee}));
+        assert_eq!("beacon=teleport:20".parse(), Ok(ScriptRule{species: Species::Beacon, script: Script::Teleport{every: 20}}));
+        assert_eq!("wall=patrol:udlrw".parse(), Ok(ScriptRule{species: Species::Wall, script: Script::Patrol{route: vec![(0, 1), (0, -1), (-1, 0), (1, 0), (0, 0)]}}));
+    }
+
+    #[test]
+    fn rejects_nonsense() {
+        for rule in ["", "wall", "psychic=flee", "wall=dance", "wall=teleport:0", "wall=teleport:soon", "wall=patrol:up", "wall=flee:3"] {
+            assert_eq!(rule.parse::<ScriptRule>(), Err(()), "{rule}");
+        }
+    }
+}
@@ -1,139 +1,334 @@
-use std::f32::consts::PI;
-use bevy::prelude::*;
-use rand::{distributions::WeightedIndex, prelude::Distribution};
+use std::{f32::consts::PI, ops::DerefMut, str::FromStr};
+use bevy::{app::AppExit, ecs::system::SystemParam, prelude::*};
+use rand::{distributions::WeightedIndex, prelude::Distribution, Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
 
-use crate::{psychics::{Position, Soul, Trace, PsychicSettings}, nn::Net, axiom::Axiom, map::{Map, Species, build_map, xy_idx}};
+use crate::{config::Config, novelty::{NoveltyArchive, blend_scores, score_novelty}, pareto::{EliteMember, ParetoElite, crowded_tournament, crowding_distances, pareto_fronts}, speciation::{Speciation, speciate}, paintball::{describe_territory, share_of, territory}, hall_of_fame::{HallOfFame, PendingTrials, induct_champion, reevaluate_champions, welcome_champions}, psychics::{HylicBundle, Position, Soul, Trace, TurnRecord}, scripts::Script, energy::Exhaustion, nn::Net, axiom::Axiom, map::{Map, Species, Topology, Neighbourhood, OutOfBounds, build_map}};
 
 pub struct SimulationPlugin;
 
 impl Plugin for SimulationPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(SimulationSettings{max_turn_number: MAX_TURN_NUMBER, current_turn: MAX_TURN_NUMBER, current_generation: 0});
-        app.add_systems(Update, simulate_generation);
-        app.add_systems(Update, evolve_generation);
+        let config = app.world.get_resource::<Config>().cloned().unwrap_or_default();
+        app.insert_resource(SimulationSettings{max_turn_number: MAX_TURN_NUMBER, current_turn: MAX_TURN_NUMBER, current_generation: 0, record_decisions: config.record_decisions});
+        let seed = config.seed.unwrap_or_else(rand::random);
+        info!("Training with seed {seed}."); // Given to --seed, it plays the same run again.
+        app.insert_resource(TrainingRng(ChaCha12Rng::seed_from_u64(seed)));
+        app.insert_resource(StatsHistory::default());
+        let evolution = EvolutionSettings{novelty_weight: config.novelty_weight, selection: config.selection, target_species: config.species, ..default()};
+        app.insert_resource(evolution.clone());
+        app.insert_resource(NextEvolutionSettings(evolution));
+        app.add_event::<GenerationFinished>();
+        app.insert_resource(NoveltyArchive::default());
+        app.insert_resource(Speciation::default());
+        app.insert_resource(ParetoElite::default());
+        app.insert_resource(HallOfFame{champions: Vec::new(), size: config.hall_size, interval: config.reevaluation_interval});
+        app.insert_resource(PendingTrials::default());
+        app.add_systems(Update, (simulate_generation, score_novelty, speciate, evolve_generation).chain());
+        app.add_systems(Update, (fill_empty_walls, apply_evolution_settings, keep_history).after(evolve_generation));
+        app.add_systems(Update, (welcome_champions, induct_champion, reevaluate_champions).chain().after(evolve_generation).before(apply_evolution_settings)); // Trials follow the rules the generation was played by.
+        if let Some(generations) = config.generations {
+            app.insert_resource(GenerationLimit(generations));
+            app.add_systems(Update, stop_training.after(evolve_generation));
+        }
         app.register_type::<SimulationSettings>();
+        app.register_type::<EvolutionSettings>();
     }
 }
 
-pub const PLAY_AREA_WIDTH: u32 = 45;
-pub const PLAY_AREA_HEIGHT: u32 = 45;
 pub const MAX_TURN_NUMBER: usize = 100;
+pub const SIGHT_RANGE: i32 = 2; // Which tiles are solid.
+pub const TOUCH_RANGE: i32 = 1; // Which tiles are painted or unpainted walls.
+
+#[derive(Event)]
+pub struct GenerationFinished { // Sent once the traces of a generation have been shipped.
+    pub generation: usize,
+    pub best_fitness: f32,
+    pub mean_fitness: f32,
+}
+
+#[derive(Resource)]
+pub struct TrainingRng(pub ChaCha12Rng); // Every draw training makes comes from here, in a fixed order. So a seed replays a run, and a checkpoint carries on with the same dice.
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct GenerationStats {
+    pub generation: usize,
+    pub best_fitness: f32,
+    pub mean_fitness: f32,
+}
+
+#[derive(Resource, Default, Clone)]
+pub struct StatsHistory(pub Vec<GenerationStats>); // One entry per finished generation since the run began, resumes included.
+
+#[derive(Resource)]
+pub struct GenerationLimit(pub usize); // Training quits once this many generations are done.
 
 #[derive(Resource, Default, Reflect)]
 #[reflect(Resource)]
 pub struct SimulationSettings {
     pub max_turn_number: usize,
     pub current_turn: usize,
-    pub current_generation: usize
+    pub current_generation: usize,
+    pub record_decisions: bool, // Keep the senses and outputs of every turn in the Traces. Costs memory.
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Reflect)]
+pub enum Selection { // How the parents of the next generation are picked.
+    Roulette, // Chance proportional to fitness.
+    Tournament, // Best of a few random ones.
+    Truncation, // Any of the top quarter, equally.
+    Pareto, // NSGA-II: each objective scored on its own. The best of parents and children survive, better front first, then the less crowded.
+}
+
+impl FromStr for Selection {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "roulette" => Ok(Selection::Roulette),
+            "tournament" => Ok(Selection::Tournament),
+            "truncation" => Ok(Selection::Truncation),
+            "pareto" => Ok(Selection::Pareto),
+            _ => Err(()),
+        }
+    }
+}
+
+const TOURNAMENT_SIZE: usize = 3;
+const TRUNCATION_SHARE: f32 = 0.25;
+
+#[derive(Clone, PartialEq, Debug, Reflect)]
+pub struct FitnessWeights {
+    pub paint: f32, // Per wall painted.
+    pub wander: f32, // Multiplier for ending far from the start. 20 times more for those who also painted a bit.
+    pub variety: f32, // Multiplier for using more than 3 motions.
+    pub painter: f32, // Multiplier for painting at all.
+    pub catch: f32, // For a Hunter, per Psychic tagged.
+    pub evade: f32, // For a Psychic, per turn not caught while there are Hunters.
+    pub territory: f32, // The whole fitness of a paintball faction member, times the share of the walls in its colours at the end.
+}
+
+#[derive(Resource, Clone, PartialEq, Debug, Reflect)]
+#[reflect(Resource)]
+pub struct EvolutionSettings { // Only ever changed between two generations, see NextEvolutionSettings.
+    pub mutation_rate: f64,
+    pub mutation_strength: f64,
+    pub selection: Selection,
+    pub fitness: FitnessWeights,
+    pub novelty_weight: f32, // 0 selects on fitness alone, 1 on novelty alone.
+    pub target_species: usize, // 0 lets the whole population compete, otherwise roughly how many species to split it in.
+    pub turns_per_frame: usize, // Large impact on performance: this number is the simulation speed.
+    pub max_turn_number: usize,
 }
 
+impl Default for EvolutionSettings {
+    fn default() -> Self {
+        Self {
+            mutation_rate: 1.,
+            mutation_strength: 0.5,
+            selection: Selection::Roulette,
+            fitness: FitnessWeights{paint: 2., wander: 5., variety: 100., painter: 20., catch: 10., evade: 0.5, territory: 100.},
+            novelty_weight: 0.,
+            target_species: 0,
+            turns_per_frame: 10,
+            max_turn_number: MAX_TURN_NUMBER,
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct NextEvolutionSettings(pub EvolutionSettings); // Free to edit at any time, applied when the current generation ends.
+
 fn simulate_generation( // Trying hard to make this concurrent with time_passes. Not sure if it will work. 10th November 2023
     // In order to make effects and spells happen: make a vector of (position, effect). Then, at the start of next turn, make them all happen. 12th November 2023
     mut config: ResMut<SimulationSettings>,
+    settings: Res<EvolutionSettings>,
     mut psychics: Query<(&mut Position, &mut Soul, &mut Trace, &mut Species), With<Soul>>,
-    mut hylics: Query<(&mut Position, &mut Trace, &mut Species), Without<Soul>>,
+    mut hylics: Query<(&mut Position, &mut Trace, &mut Species, &Script), Without<Soul>>,
     mut map: ResMut<Map>,
+    mut rng: ResMut<TrainingRng>,
 ){    
     if config.current_turn == config.max_turn_number{
         return;
     }
     assert!(config.current_turn < config.max_turn_number);
-    for _turn in 0..10{ // large impact on performance: this number is the simulation speed
-        let mut beacon_of_light: (u32, u32) = (0,0);
-        for (mut position, _trace, mut species) in hylics.iter_mut(){
-            // Each entity can do an action by itself.
-            map = exit_tile(map, position.x, position.y);
+    let mut hylics: Vec<Body> = hylics.iter_mut().map(|(position, trace, species, script)| Body{position: position.into_inner(), trace: trace.into_inner(), species: species.into_inner(), script}).collect();
+    hylics.sort_by_key(|body| (body.position.starting_position.1, body.position.starting_position.0)); // The query order depends on when each was spawned, a resumed run would roll the dice for them in another order.
+    let mut minds: Vec<Mind> = psychics.iter_mut().map(|(position, soul, trace, species)| Mind{position: position.into_inner(), soul: soul.into_inner(), trace: trace.into_inner(), species: species.into_inner()}).collect();
+    for _turn in 0..settings.turns_per_frame{
+        if config.current_turn == config.max_turn_number {
+            break; // Do not overshoot when the generation length is not a multiple of the turns per frame.
+        }
+        play_turn(&mut map, config.current_turn, &mut hylics, &mut minds, &settings, config.record_decisions, &mut rng.0);
+        config.current_turn += 1;
+    }
+}
 
-            let action = Axiom::Move { dx: 0, dy: 0 };
-            let performance;
-            ((position.x, position.y), performance) = process_motion(position.x, position.y, action, &map.tiles);
-            *species = process_metamorphosis(action, *species);
-            let performance;
-            (map, performance) = process_axioms(map, action, (position.x, position.y));
+pub struct Body<'a> { // A Hylic, borrowed from wherever it lives: the ECS in training, a Vec in a champion's trial.
+    pub position: &'a mut Position,
+    pub trace: &'a mut Trace,
+    pub species: &'a mut Species,
+    pub script: &'a Script,
+}
 
-            map = enter_tile(map, position.x, position.y, *species);
+pub struct Mind<'a> { // Same, for a Psychic.
+    pub position: &'a mut Position,
+    pub soul: &'a mut Soul,
+    pub trace: &'a mut Trace,
+    pub species: &'a mut Species,
+}
 
-            // This is terrible and should be removed.
-            match *species{
-                Species::Beacon => beacon_of_light = (position.x, position.y),
-                _ => ()
-            }
+pub fn play_turn( // One turn of the game. Training and the hall of fame's trials both go through here, so they cannot drift apart.
+    map: &mut Map,
+    turn: usize,
+    hylics: &mut [Body],
+    minds: &mut [Mind],
+    settings: &EvolutionSettings,
+    record_decisions: bool,
+    rng: &mut impl Rng,
+){
+    let mut map = map;
+    let mut beacon_of_light: (u32, u32) = (0,0);
+    for Body{position, trace, species, script} in hylics.iter_mut(){
+        if position.benched {
+            continue;
+        }
+        // Each entity can do an action by itself.
+        let action = script.act((position.x, position.y), turn, map, rng);
+        map = exit_tile(map, position.x, position.y);
+
+        trace.actions.push(action);
+        let performance;
+        ((position.x, position.y), performance) = process_motion(position.x, position.y, action, map);
+        **species = process_metamorphosis(action, **species);
+        let performance;
+        (map, performance) = process_axioms(map, action, (position.x, position.y), &settings.fitness);
+
+        map = enter_tile(map, position.x, position.y, **species);
+
+        // This is terrible and should be removed.
+        match **species{
+            Species::Beacon => beacon_of_light = (position.x, position.y),
+            _ => ()
         }
-        for (mut position, mut soul, mut _trace, mut species) in psychics.iter_mut(){
+    }
+    for hunting in [false, true]{ // The Psychics move first, then the Hunters go after wherever they ended up. So a tag never misses.
+        for Mind{position, soul, trace, species} in minds.iter_mut(){
+            if (**species == Species::Hunter) != hunting {
+                continue;
+            }
+            if turn == 0 { // Spawned or reset since the last turn, either way a fresh start.
+                soul.energy = map.energy.map_or(0., |rules| rules.capacity);
+            }
+            let exhausted = map.energy.is_some() && soul.energy <= 0.; // Frozen, or gone from the map when Species::Nothing.
+            if **species == Species::Caught || exhausted { // Out until the generation ends.
+                trace.actions.push(Axiom::Void);
+                if record_decisions {
+                    trace.records.push(TurnRecord{senses: soul.senses_input.clone(), outputs: Vec::new(), action: Axiom::Void, fitness_delta: 0.});
+                }
+                continue;
+            }
             //soul.senses_input = locate_quadrant(position.x, position.y, beacon_of_light.0, beacon_of_light.1);
-            soul.senses_input = find_near_collisions((position.x, position.y), &map.tiles, 2);
-            soul.senses_input.append(&mut find_near_of_species((position.x, position.y), &map.tiles, Species::TermiPainted, 1));
-            soul.senses_input.append(&mut find_near_of_species((position.x, position.y), &map.tiles, Species::Wall, 1));
+            soul.senses_input = gather_senses((position.x, position.y), map, soul.energy);
             //dbg!(soul.senses_input.len());
             //soul.senses_input.append(&mut vec![10./(10.+((position.x as i32 - beacon_of_light.0 as i32).abs() + (position.y as i32 - beacon_of_light.1 as i32).abs()) as f64)]);
             soul.decision_outputs = soul.nn.decide(&soul.senses_input);
             let index_of_biggest = soul.decision_outputs.iter().enumerate().fold((0, 0.0), |max, (ind, &val)| if val > max.1 {(ind, val)} else {max});
             let action = soul.action_choices[index_of_biggest.0];
+            trace.actions.push(action);
+            let fitness_before = soul.fitness;
             if !soul.actions_chosen.contains(&action.act_motion()){ soul.actions_chosen.push(action.act_motion())};
-            if action == (Axiom::PaintAdjacent { color: Species::TermiPainted}) && !soul.actions_chosen.contains(&(0,0)) { soul.actions_chosen.push((0,0))};
+            if matches!(action, Axiom::PaintAdjacent{..}) && !soul.actions_chosen.contains(&(0,0)) { soul.actions_chosen.push((0,0))};
             // Each entity can do an action by itself.
             map = exit_tile(map, position.x, position.y);
             let performance;
-            ((position.x, position.y), performance) = process_motion(position.x, position.y, action, &map.tiles);
+            ((position.x, position.y), performance) = process_motion(position.x, position.y, action, map);
             soul.fitness += performance as f32;
-            *species = process_metamorphosis(action, *species);
+            **species = process_metamorphosis(action, **species);
             let performance;
-            (map, performance) = process_axioms(map, action, (position.x, position.y));
-            soul.fitness += performance as f32;
+            (map, performance) = process_axioms(map, action, (position.x, position.y), &settings.fitness);
+            if !map.rival_senses || action == Axiom::Tag { // Against rivals, only catching and evading count.
+                soul.fitness += performance;
+            }
+            if **species == Species::Psychic && map.rival_senses { // Still free at the end of its turn.
+                soul.fitness += settings.fitness.evade;
+            }
+            if let Some(rules) = map.energy {
+                soul.energy = rules.spend(soul.energy, action, (position.x, position.y), map);
+                if soul.energy <= 0. && rules.exhaustion == Exhaustion::Die {
+                    **species = Species::Nothing; // Entering as Nothing below frees its tile for good.
+                }
+            }
             //dbg!(performance);
+            if record_decisions {
+                trace.records.push(TurnRecord{
+                    senses: soul.senses_input.clone(),
+                    outputs: soul.decision_outputs.clone(),
+                    action,
+                    fitness_delta: soul.fitness - fitness_before,
+                });
+            }
 
-            map = enter_tile(map, position.x, position.y, *species);
+            map = enter_tile(map, position.x, position.y, **species);
         }
-        //debug_print_axiom_map(&map);
-        for (mut position, mut trace, mut species) in hylics.iter_mut(){
-            map = exit_tile(map, position.x, position.y);
-            // Then, the Axiom effects happen.
-            let action = grab_axiom_at_pos(&map.axiom_map, (position.x, position.y)); // This makes it impossible to stack multiple axioms in one location, it might need to be changed to a vector.       
-            map = void_axiom_at(map, (position.x, position.y));
-            let performance;
-            ((position.x, position.y), performance) = process_motion(position.x, position.y, action, &map.tiles);
-            *species = process_metamorphosis(action, *species);
-            let performance;
-            (map, performance) = process_axioms(map, action, (position.x, position.y));
-
-            map = enter_tile(map, position.x, position.y, *species);
-            trace.positions.push((position.x, position.y));
-            trace.identity.push(*species);
+    }
+    //debug_print_axiom_map(&map);
+    for Body{position, trace, species, ..} in hylics.iter_mut(){
+        if position.benched {
+            continue;
         }
-        for (mut position, mut _soul, mut trace, mut species) in psychics.iter_mut(){
-            map = exit_tile(map, position.x, position.y);
-
-            let action = grab_axiom_at_pos(&map.axiom_map, (position.x, position.y));
-            map = void_axiom_at(map, (position.x, position.y));
-            let performance;
-            ((position.x, position.y), performance) = process_motion(position.x, position.y, action, &map.tiles);
-            *species = process_metamorphosis(action, *species);
-            let performance;
-            (map, performance) = process_axioms(map, action, (position.x, position.y));
-
-            map = enter_tile(map, position.x, position.y, *species);
+        map = exit_tile(map, position.x, position.y);
+        // Then, the Axiom effects happen.
+        let action = grab_axiom_at_pos(map, (position.x, position.y)); // This makes it impossible to stack multiple axioms in one location, it might need to be changed to a vector.       
+        map = void_axiom_at(map, (position.x, position.y));
+        let performance;
+        ((position.x, position.y), performance) = process_motion(position.x, position.y, action, map);
+        **species = process_metamorphosis(action, **species);
+        let performance;
+        (map, performance) = process_axioms(map, action, (position.x, position.y), &settings.fitness);
+
+        map = enter_tile(map, position.x, position.y, **species);
+        trace.positions.push((position.x, position.y));
+        trace.identity.push(**species);
+    }
+    for Mind{position, trace, species, ..} in minds.iter_mut(){
+        if **species == Species::Nothing { // Died of exhaustion, someone else may be standing there by now.
             trace.positions.push((position.x, position.y));
-            trace.identity.push(*species);
+            trace.identity.push(**species);
+            continue;
         }
-        config.current_turn += 1;
+        map = exit_tile(map, position.x, position.y);
+
+        let action = grab_axiom_at_pos(map, (position.x, position.y));
+        map = void_axiom_at(map, (position.x, position.y));
+        let performance;
+        ((position.x, position.y), performance) = process_motion(position.x, position.y, action, map);
+        **species = process_metamorphosis(action, **species);
+        let performance;
+        (map, performance) = process_axioms(map, action, (position.x, position.y), &settings.fitness);
+
+        map = enter_tile(map, position.x, position.y, **species);
+        trace.positions.push((position.x, position.y));
+        trace.identity.push(**species);
     }
 }
 
-pub fn process_axioms(
-    mut map: ResMut<Map>,
+pub fn process_axioms<M: DerefMut<Target = Map>>( // Generic so champions can be run on a Map which is not the Resource.
+    mut map: M,
     action: Axiom,
     cur_pos: (u32, u32),
-)-> (ResMut<Map>, i16){
+    rewards: &FitnessWeights,
+)-> (M, f32){
     let effects = action.act_axioms(cur_pos, &map);
-    let mut performance = 0;
-    if effects.is_empty() {return (map, 0);}
+    let mut performance = 0.;
+    if effects.is_empty() {return (map, 0.);}
     for i in effects{
         let idx = map.xy_idx(i.1.0, i.1.1);
         map.axiom_map[idx] = i.0;
-        if i.0 != Axiom::Void{
-            performance += 2;
-        }
-        else { performance += -1;}
+        performance += match i.0 {
+            Axiom::Void => if map.energy.is_some() { 0. } else { -1. }, // With energy, the paint wasted is penalty enough.
+            Axiom::SpeciesTransform { new_species: Species::Caught } => rewards.catch,
+            _ => rewards.paint,
+        };
         
     }
     (map, performance)
@@ -149,25 +344,28 @@ pub fn debug_print_axiom_map(
             _ => '#'
         };
         string.push(char);
-        if string.len() == 45{
+        if string.len() == map.width as usize{
             dbg!(string);
             string = String::from("");
         }
     }
 }
 
-pub fn void_axiom_at(
-    mut map: ResMut<Map>,
+pub fn void_axiom_at<M: DerefMut<Target = Map>>(
+    mut map: M,
     pos: (u32, u32)
-) -> ResMut<Map>{
+) -> M{
     let idx = map.xy_idx(pos.0, pos.1);
     map.axiom_map[idx] = Axiom::Void;
     map
 }
 
-pub fn process_x(new_pos: i32) -> i32 {
-    match new_pos >= PLAY_AREA_WIDTH as i32{
-        true => PLAY_AREA_WIDTH as i32-1,
+pub fn process_x(new_pos: i32, map: &Map) -> i32 {
+    if map.topology == Topology::Toroidal {
+        return new_pos.rem_euclid(map.width as i32);
+    }
+    match new_pos >= map.width as i32{
+        true => map.width as i32-1,
         false => match new_pos < 0 {
             true => 0,
             false => new_pos
@@ -175,9 +373,12 @@ pub fn process_x(new_pos: i32) -> i32 {
     }
 }
 
-pub fn process_y(new_pos: i32) -> i32 {
-    match new_pos >= PLAY_AREA_HEIGHT as i32{
-        true => PLAY_AREA_HEIGHT as i32-1,
+pub fn process_y(new_pos: i32, map: &Map) -> i32 {
+    if map.topology == Topology::Toroidal {
+        return new_pos.rem_euclid(map.height as i32);
+    }
+    match new_pos >= map.height as i32{
+        true => map.height as i32-1,
         false => match new_pos < 0 {
             true => 0,
             false => new_pos
@@ -185,92 +386,124 @@ pub fn process_y(new_pos: i32) -> i32 {
     }
 }
 
-pub fn enter_tile(mut map: ResMut<Map>, x: u32, y: u32, species: Species) -> ResMut<Map>{
+pub fn enter_tile<M: DerefMut<Target = Map>>(mut map: M, x: u32, y: u32, species: Species) -> M{
     let idx = map.xy_idx(x, y);
     map.tiles[idx] = species;
     map
 }
 
-pub fn exit_tile(mut map: ResMut<Map>, x: u32, y: u32) -> ResMut<Map>{
+pub fn exit_tile<M: DerefMut<Target = Map>>(mut map: M, x: u32, y: u32) -> M{
     let idx = map.xy_idx(x, y);
     map.tiles[idx] = Species::Nothing;
     map
 }
 
-pub fn get_adjacent_coords(
+pub fn get_adjacent_coords( // Every distinct tile in the neighbourhood. Whatever fell off the edge is left out.
     pos: (u32, u32),
-    range: i32
+    neighbourhood: Neighbourhood,
+    map: &Map,
 ) -> Vec<(u32, u32)>{
-    let mut search = Vec::with_capacity((range*8) as usize);
-    let mut output = Vec::with_capacity((range*8) as usize);
-    for i in -range..=range{
-        for j in -range..=range{
-            search.push((i,j));
+    let mut output = Vec::with_capacity(neighbourhood.size());
+    for (dx, dy) in neighbourhood.offsets(){
+        let Some(new_coords) = map.resolve(pos.0 as i32 + dx, pos.1 as i32 + dy, neighbourhood.out_of_bounds) else { continue };
+        if !output.contains(&new_coords){ // Small wrapped arenas can reach the same tile twice.
+            output.push(new_coords);
         }
     }
-    for i in search{
-        let new_coords = (process_x(pos.0 as i32+i.0) as u32, process_y(pos.1 as i32+i.1) as u32);
-        output.push(new_coords);
-    }
     output
 }
 
+pub fn get_adjacent_species( // One entry per offset no matter what, the neural networks need a fixed number of inputs.
+    pos: (u32, u32),
+    neighbourhood: Neighbourhood,
+    map: &Map,
+) -> Vec<Species>{
+    let mut output = Vec::with_capacity(neighbourhood.size());
+    for (dx, dy) in neighbourhood.offsets(){
+        let species = match map.resolve(pos.0 as i32 + dx, pos.1 as i32 + dy, neighbourhood.out_of_bounds) {
+            Some((x, y)) => map.tiles[map.xy_idx(x, y)],
+            None => match neighbourhood.out_of_bounds {
+                OutOfBounds::Empty => Species::Nothing,
+                _ => Species::Wall,
+            },
+        };
+        output.push(species);
+    }
+    output
+}
 
 pub fn find_near_collisions(
     pos: (u32, u32),
-    collision_map: &[Species],
-    range: i32
+    map: &Map,
+    neighbourhood: Neighbourhood,
 ) -> Vec<f64>{
-    let mut output = Vec::with_capacity(4);
-    for i in get_adjacent_coords(pos, range){
-        if target_is_empty(i, collision_map){
-            output.push(1.);
-        } else {output.push(0.)};
-    }
-    output
+    find_near_of_species(pos, map, Species::Nothing, neighbourhood)
 }
 
 pub fn find_near_of_species(
     pos: (u32, u32),
-    collision_map: &[Species],
+    map: &Map,
     species: Species,
-    range: i32,
+    neighbourhood: Neighbourhood,
 ) -> Vec<f64>{
-    let mut output = Vec::with_capacity(4);
-    for i in get_adjacent_coords(pos, range){
-        if target_is_of_species(i, collision_map, species){
+    let mut output = Vec::with_capacity(neighbourhood.size());
+    for i in get_adjacent_species(pos, neighbourhood, map){
+        if i == species{
             output.push(1.);
         } else {output.push(0.)};
     }
     output
 }
 
+pub fn gather_senses(
+    pos: (u32, u32),
+    map: &Map,
+    energy: f32, // Ignored without energy rules.
+) -> Vec<f64>{
+    let mut output = find_near_collisions(pos, map, map.sense_neighbourhood(SIGHT_RANGE));
+    let own = map.tiles[map.xy_idx(pos.0, pos.1)];
+    output.append(&mut find_near_of_species(pos, map, own.paint(), map.sense_neighbourhood(TOUCH_RANGE)));
+    output.append(&mut find_near_of_species(pos, map, Species::Wall, map.sense_neighbourhood(TOUCH_RANGE)));
+    if let (true, Some(opponent)) = (map.teams, own.opponent()) { // The walls the other faction holds.
+        output.append(&mut find_near_of_species(pos, map, opponent.paint(), map.sense_neighbourhood(TOUCH_RANGE)));
+    }
+    if map.rival_senses {
+        let rival = match map.tiles[map.xy_idx(pos.0, pos.1)] {
+            Species::Hunter => Species::Psychic,
+            _ => Species::Hunter,
+        };
+        output.append(&mut find_near_of_species(pos, map, rival, map.sense_neighbourhood(SIGHT_RANGE)));
+    }
+    if let Some(rules) = map.energy {
+        output.push((energy / rules.capacity) as f64);
+    }
+    output
+}
+
+pub fn sense_count(map: &Map) -> usize { // Size of the neural networks' input layer.
+    let rivals = if map.rival_senses { map.sense_neighbourhood(SIGHT_RANGE).size() } else { 0 };
+    let opponents = if map.teams { map.sense_neighbourhood(TOUCH_RANGE).size() } else { 0 };
+    let energy = if map.energy.is_some() { 1 } else { 0 };
+    map.sense_neighbourhood(SIGHT_RANGE).size() + 2 * map.sense_neighbourhood(TOUCH_RANGE).size() + rivals + opponents + energy
+}
+
 pub fn grab_axiom_at_pos(
-    axiom_map: &[Axiom],
+    map: &Map,
     pos: (u32, u32),
 ) -> Axiom {
-    let idx = xy_idx(pos.0, pos.1);
-    axiom_map[idx]
+    let idx = map.xy_idx(pos.0, pos.1);
+    map.axiom_map[idx]
 }
 
 pub fn target_is_empty(
     new_pos: (u32, u32),
-    collision_map: &[Species],
+    map: &Map,
 ) -> bool {
-    let idx = xy_idx(new_pos.0, new_pos.1);
-    collision_map[idx] == Species::Nothing
+    let idx = map.xy_idx(new_pos.0, new_pos.1);
+    map.tiles[idx] == Species::Nothing
 }
 
-pub fn target_is_of_species(
-    new_pos: (u32, u32),
-    collision_map: &[Species],
-    species: Species,
-) -> bool {
-    let idx = xy_idx(new_pos.0, new_pos.1);
-    collision_map[idx] == species
-}
-
-fn process_metamorphosis(
+pub fn process_metamorphosis(
     action: Axiom,
     species: Species
 ) -> Species {
@@ -282,15 +515,18 @@ fn process_metamorphosis(
     spe
 }
 
-fn process_motion(
+pub fn process_motion(
     cur_x: u32,
     cur_y: u32,
     action: Axiom,
-    collision_map: &[Species],
+    map: &Map,
 ) -> ((u32, u32), i16){
     let (dx, dy) = action.act_motion();
-    let new_coords = (process_x(cur_x as i32 + dx) as u32, process_y(cur_y as i32 + dy) as u32);
-    if target_is_empty(new_coords, collision_map) || new_coords == (cur_x, cur_y) { //
+    let new_coords = match action {
+        Axiom::Teleport { x, y } => (x, y),
+        _ => (process_x(cur_x as i32 + dx, map) as u32, process_y(cur_y as i32 + dy, map) as u32),
+    };
+    if target_is_empty(new_coords, map) || new_coords == (cur_x, cur_y) { //
         (new_coords, 0)
     } else { ((cur_x, cur_y), 0) }   
 }
@@ -326,10 +562,19 @@ fn locate_quadrant( // Move this to a Senses file later
     output.to_vec()
 }
 
-fn evolve_generation(
+#[derive(SystemParam)]
+pub struct Lineage<'w> { // What decides who parents the next generation, besides the generation itself.
+    speciation: ResMut<'w, Speciation>,
+    elite: ResMut<'w, ParetoElite>,
+    rng: ResMut<'w, TrainingRng>,
+}
+
+pub fn evolve_generation(
     mut config: ResMut<SimulationSettings>,
+    settings: Res<EvolutionSettings>,
+    mut finished: EventWriter<GenerationFinished>,
     mut psychics: Query<(&mut Position, &mut Soul, &mut Trace, &mut Species), With<Soul>>, // Consider making this the same query with Has<Soul>
-    psy_settings: Res<PsychicSettings>,
+    mut lineage: Lineage,
     mut hylics: Query<(&mut Position, &mut Trace, &mut Species), Without<Soul>>,
     mut map: ResMut<Map>,
 
@@ -337,22 +582,29 @@ fn evolve_generation(
     if config.current_turn < config.max_turn_number{
         return;
     }
-    (map.tiles, map.catalogue, map.locations, map.axiom_map) = build_map(map.population.clone());
+    let territory = territory(&map.tiles); // Before the walls are rebuilt clean.
+    if map.teams {
+        info!("Generation {}: territory {}.", config.current_generation, describe_territory(&territory));
+    }
+    map.shipped_tiles = map.starting_tiles.clone();
+    (map.shipped_width, map.shipped_height) = (map.width, map.height);
+    (map.tiles, map.catalogue, map.locations, map.axiom_map) = build_map(map.population.clone(), map.width, map.height, map.topology, &mut lineage.rng.0);
+    map.starting_tiles = map.tiles.clone();
     let mut beacon_of_light: (u32, u32) = (0,0); // Very gory when more Hylics will get added.
     for (mut pos, mut trace, mut species) in hylics.iter_mut(){
         trace.shipped_positions = trace.positions.clone();
         trace.shipped_identity = trace.identity.clone();
+        trace.shipped_actions = trace.actions.clone();
         trace.identity = Vec::with_capacity(config.max_turn_number);
+        trace.actions = Vec::with_capacity(config.max_turn_number);
         trace.positions = Vec::with_capacity(config.max_turn_number);
         let creature = trace.original_species;
         let index = map.catalogue.iter().position(|r| r == &creature).unwrap();
-        if map.locations[index].is_empty(){
-            trace.positions.push((0, 0));
-            // Super gory. Since we're always stuck with too many walls, some of them can't find a position and get tucked in a stack in the corner instead. Fix this.
-            break;
-        }
-        let Some((x,y)) = map.locations[index].pop() else { 
-            panic!("Locations assigment did not find an XY pair.") };
+        let Some((x,y)) = map.locations[index].pop() else {
+            pos.benched = true; // This map has fewer of its kind than the last one. It will ship nothing next generation.
+            continue;
+        };
+        pos.benched = false;
         (pos.x, pos.y) = (x, y);
         pos.starting_position = (x,y);
         *species = creature;
@@ -363,8 +615,13 @@ fn evolve_generation(
             _ => ()
         }
     }
-    let mut all_souls: Vec<Net> = Vec::with_capacity(psy_settings.number_at_start as usize); 
-    let mut all_fitnesses: Vec<f32> = Vec::with_capacity(psy_settings.number_at_start as usize);
+    let population = psychics.iter().len();
+    let mut all_souls: Vec<Net> = Vec::with_capacity(population); 
+    let mut all_fitnesses: Vec<f32> = Vec::with_capacity(population);
+    let mut all_novelties: Vec<f32> = Vec::with_capacity(population);
+    let mut all_objectives: Vec<Vec<f32>> = Vec::with_capacity(population);
+    let mut all_clades: Vec<Option<usize>> = Vec::with_capacity(population);
+    let mut all_populations: Vec<(Species, bool)> = Vec::with_capacity(population); // Which one it belongs to, and whether it only stood in for an archived rival.
     let mut best_fit = (0., 0);
     for (mut pos, mut soul, mut trace, mut species) in psychics.iter_mut(){
         let creature = trace.original_species;
@@ -373,17 +630,26 @@ fn evolve_generation(
             dbg!(&map.locations[index]);
             dbg!(index);
             panic!("Locations assigment did not find an XY pair.") };
-        if (pos.x as i32 - pos.starting_position.0 as i32).abs() > 4 && (pos.y as i32 - pos.starting_position.1 as i32).abs() > 4 {
-            if soul.fitness > 8. {
-                soul.fitness *= 100.
-            }
-            else {soul.fitness *= 5.};
+        let mut variety: Vec<Axiom> = Vec::new();
+        for action in trace.actions.iter(){
+            if !variety.contains(action) { variety.push(*action) };
         }
+        let objectives = vec![ // Same order as pareto::OBJECTIVES, taken before the multipliers below mix them up.
+            map.distance(pos.starting_position, (pos.x, pos.y)) as f32,
+            soul.fitness,
+            variety.len() as f32,
+            trace.actions.iter().filter(|a| **a != Axiom::Void && a.act_motion() == (0, 0)).count() as f32, // Turns spent standing still or painting. Not the frozen or caught ones.
+        ];
+        soul.fitness = final_fitness(soul.fitness, (pos.starting_position, (pos.x, pos.y)), &soul.actions_chosen, creature, &territory, &map, &settings.fitness);
         (pos.x, pos.y) = (x, y);
         *species = creature;
         pos.starting_position = (x,y);
         trace.shipped_positions = trace.positions.clone();
         trace.shipped_identity = trace.identity.clone();
+        trace.shipped_actions = trace.actions.clone();
+        trace.shipped_records = std::mem::take(&mut trace.records);
+        trace.shipped_brain = Some(soul.nn.clone());
+        trace.actions = Vec::with_capacity(config.max_turn_number);
         trace.identity = Vec::with_capacity(config.max_turn_number); // Can't believe I wasted 5 hours figuring out a mysterious bug only to realize I forgot to empty the trace.identity after each generation lolololol 19th of november 2023
         trace.positions = Vec::with_capacity(config.max_turn_number);
         trace.positions.push((x, y));
@@ -392,38 +658,203 @@ fn evolve_generation(
             Species::Beacon => beacon_of_light = (pos.x, pos.y),
             _ => ()
         }
-        if soul.fitness > 30. {
-            soul.fitness *= 12.;
-        }
-        if soul.actions_chosen.len() > 3{
-
-            soul.fitness *= 100.;
-        }
-        if soul.actions_chosen.contains(&(0,0)){
-
-            soul.fitness *= 20.;
-        }
-        if soul.fitness <= 0. {soul.fitness = 1.};
+        trace.shipped_fitness = soul.fitness;
+        trace.shipped_objectives = objectives.clone();
+        all_objectives.push(objectives);
         all_souls.push(soul.nn.clone());
         all_fitnesses.push(soul.fitness);
+        all_novelties.push(soul.novelty);
+        all_clades.push(soul.clade);
+        all_populations.push((creature, soul.stand_in));
         if soul.fitness > best_fit.0{
             best_fit = (soul.fitness, all_fitnesses.len()-1);
         }
     }
     //dbg!(all_fitnesses.clone());
-    let (_max_fitness, gene_pool) = create_gene_pool(all_fitnesses);
-    let mut rng = rand::thread_rng();
-    for (mut _position, mut soul, mut _trace, _species) in psychics.iter_mut(){
-        let soul_idx = gene_pool.sample(&mut rng);
-        let mut rand_soul = all_souls[soul_idx].clone(); // soul_idx
-        rand_soul.mutate();
+    let scores = blend_scores(&all_fitnesses, &all_novelties, settings.novelty_weight);
+    let fronts = pareto_fronts(&all_objectives); // Worked out whatever the selection, the theatre shows them.
+    let crowding = crowding_distances(&all_objectives, &fronts);
+    let everyone: Vec<usize> = (0..scores.len()).collect();
+    let plan = match settings.target_species {
+        0 => Vec::new(),
+        _ => {
+            let plan = lineage.speciation.plan_offspring(&all_clades, &all_fitnesses, &scores);
+            info!("Generation {}: {} species.\n{}", config.current_generation, lineage.speciation.clades.len(), lineage.speciation.summary());
+            plan
+        }
+    };
+    match settings.selection {
+        Selection::Pareto => { // Parents come from the elite, which this generation now competes to join. Species are not kept apart then.
+            let mut sizes: Vec<(Species, usize)> = Vec::new();
+            for (population, _) in all_populations.iter(){
+                match sizes.iter_mut().find(|(s, _)| s == population) {
+                    Some((_, size)) => *size += 1,
+                    None => sizes.push((*population, 1)),
+                }
+            }
+            let newcomers = (0..all_souls.len())
+                .filter(|i| !all_populations[*i].1) // A stand-in's brain is an old champion, it was judged already.
+                .map(|i| EliteMember{brain: all_souls[i].clone(), objectives: all_objectives[i].clone(), population: all_populations[i].0, front: 0, crowding: 0.})
+                .collect();
+            lineage.elite.renew(newcomers, &sizes);
+        },
+        _ => lineage.elite.members.clear(), // Stale by the time Pareto gets picked again.
+    }
+    let rng = &mut lineage.rng.0;
+    for (i, (mut _position, mut soul, mut trace, _species)) in psychics.iter_mut().enumerate(){
+        trace.shipped_front = fronts.get(i).copied(); // Same query order as the loop above.
+        let candidates = plan.get(i).filter(|c| !c.is_empty()).unwrap_or(&everyone); // When speciating, each child slot belongs to one species.
+        let candidates = same_population(candidates, &all_populations, i).or_else(|| same_population(&everyone, &all_populations, i)).unwrap_or_else(|| vec![i]);
+        let mut rand_soul = match lineage.elite.pick(all_populations[i].0, rng) {
+            Some(parent) => parent.clone(),
+            None => all_souls[pick_parent(settings.selection, &candidates, &scores, &fronts, &crowding, rng)].clone(),
+        };
+        rand_soul.mutate(settings.mutation_rate, settings.mutation_strength, rng);
         soul.nn = rand_soul;
         soul.fitness = 0.01;
     }
+    let mean_fitness = all_fitnesses.iter().sum::<f32>() / all_fitnesses.len().max(1) as f32;
+    finished.send(GenerationFinished{generation: config.current_generation, best_fitness: best_fit.0, mean_fitness});
     config.current_turn = 0 ;
     config.current_generation += 1;
 }
 
+pub fn final_fitness( // What selection goes by, depending on the game being played.
+    raw: f32,
+    (start, end): ((u32, u32), (u32, u32)),
+    actions_chosen: &[(i32, i32)],
+    species: Species,
+    territory: &[(Species, f32)],
+    map: &Map,
+    weights: &FitnessWeights,
+) -> f32 {
+    if map.teams { // Teammates share one score: how much of the arena their colours hold.
+        return (weights.territory * share_of(territory, species)).max(1.);
+    }
+    if map.rival_senses { // The multipliers would bury the catches and evasions under millions.
+        return raw.max(1.);
+    }
+    score_fitness(raw, start, end, actions_chosen, map, weights)
+}
+
+pub fn score_fitness( // Turns what was earned during the generation into the fitness selection goes by.
+    raw: f32,
+    start: (u32, u32),
+    end: (u32, u32),
+    actions_chosen: &[(i32, i32)],
+    map: &Map,
+    weights: &FitnessWeights,
+) -> f32 {
+    let mut fitness = raw;
+    if map.distance((start.0, 0), (end.0, 0)) > 4 && map.distance((0, start.1), (0, end.1)) > 4 { // Across both axes, the short way round on a torus.
+        if fitness > 8. {
+            fitness *= weights.wander * 20.
+        }
+        else {fitness *= weights.wander};
+    }
+    if fitness > 30. {
+        fitness *= 12.;
+    }
+    if actions_chosen.len() > 3{
+        fitness *= weights.variety;
+    }
+    if actions_chosen.contains(&(0,0)){
+        fitness *= weights.painter;
+    }
+    if fitness <= 0. {fitness = 1.};
+    fitness
+}
+
+fn fill_empty_walls( // This map has more of some kind than ever before. Without a Hylic there, a tile could get painted and never show it.
+    mut commands: Commands,
+    mut finished: EventReader<GenerationFinished>,
+    map: Res<Map>,
+){
+    if finished.read().count() == 0 {
+        return;
+    }
+    for (index, creature) in map.catalogue.iter().enumerate(){ // evolve_generation took the locations it could fill, these are the leftovers.
+        for &(x, y) in map.locations[index].iter(){
+            commands.spawn(HylicBundle::new().with_position(x, y).with_species(*creature).with_script(map.script_for(*creature)).with_first_turn());
+        }
+    }
+}
+
+fn keep_history(
+    mut finished: EventReader<GenerationFinished>,
+    mut history: ResMut<StatsHistory>,
+){
+    for event in finished.read(){
+        if event.generation > 0 { // Generation 0 is the empty one evolved on startup.
+            history.0.push(GenerationStats{generation: event.generation, best_fitness: event.best_fitness, mean_fitness: event.mean_fitness});
+        }
+    }
+}
+
+fn apply_evolution_settings( // The generation boundary, the only safe moment to change the rules.
+    mut finished: EventReader<GenerationFinished>,
+    next: Res<NextEvolutionSettings>,
+    mut settings: ResMut<EvolutionSettings>,
+    mut config: ResMut<SimulationSettings>,
+){
+    let Some(event) = finished.read().last() else { return };
+    if *settings != next.0 {
+        *settings = next.0.clone();
+        info!("New evolution settings from generation {} on.", event.generation + 1);
+    }
+    if config.max_turn_number != settings.max_turn_number {
+        config.max_turn_number = settings.max_turn_number;
+    }
+}
+
+fn stop_training(
+    mut finished: EventReader<GenerationFinished>,
+    limit: Res<GenerationLimit>,
+    mut exit: EventWriter<AppExit>,
+){
+    for event in finished.read(){
+        if event.generation >= limit.0 { // Generation 0 is the empty one evolved on startup.
+            info!("Finished training {} generations.", limit.0);
+            exit.send(AppExit);
+        }
+    }
+}
+
+fn same_population( // Hunters only breed with Hunters, and nobody with a stand-in: its brain is an old champion, not one of this generation.
+    candidates: &[usize],
+    populations: &[(Species, bool)],
+    child: usize,
+) -> Option<Vec<usize>> {
+    let parents: Vec<usize> = candidates.iter().copied().filter(|p| populations[*p].0 == populations[child].0 && !populations[*p].1).collect();
+    if parents.is_empty() { None } else { Some(parents) }
+}
+
+fn pick_parent( // One of the candidates, picked the way the settings say.
+    selection: Selection,
+    candidates: &[usize],
+    scores: &[f32],
+    fronts: &[usize],
+    crowding: &[f32],
+    rng: &mut impl Rng,
+) -> usize {
+    match selection {
+        Selection::Roulette => {
+            let (_max_fitness, gene_pool) = create_gene_pool(candidates.iter().map(|i| scores[*i]).collect());
+            candidates[gene_pool.sample(rng)]
+        },
+        Selection::Tournament => (0..TOURNAMENT_SIZE).map(|_| candidates[rng.gen_range(0..candidates.len())]).max_by(|a, b| scores[*a].total_cmp(&scores[*b])).unwrap(),
+        Selection::Truncation => {
+            let mut ranking = candidates.to_vec();
+            ranking.sort_by(|a, b| scores[*b].total_cmp(&scores[*a]));
+            ranking[rng.gen_range(0..((ranking.len() as f32 * TRUNCATION_SHARE).ceil() as usize).max(1))]
+        },
+        Selection::Pareto => {
+            let (a, b) = (candidates[rng.gen_range(0..candidates.len())], candidates[rng.gen_range(0..candidates.len())]);
+            crowded_tournament(a, b, fronts, crowding)
+        },
+    }
+}
+
 fn create_gene_pool(values: Vec<f32>) -> (f32, WeightedIndex<f32>) {
     let mut max_fitness = 0.0;
     let mut weights = Vec::new();
@@ -0,0 +1,240 @@
+use bevy::prelude::*;
+
+use crate::{nn::Net, psychics::Soul, simulation::{EvolutionSettings, SimulationSettings}};
+
+pub struct SpeciationPlugin; // Only the HUD, the SimulationPlugin does the actual work.
+
+impl Plugin for SpeciationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, draw_clade_label);
+        app.add_systems(Update, update_clade_label);
+    }
+}
+
+const STARTING_THRESHOLD: f64 = 0.7; // Average weight gap under which two brains belong together.
+const THRESHOLD_STEP: f64 = 0.05; // The threshold drifts by this much each generation, to get closer to the wanted number of species.
+const STAGNATION_LIMIT: usize = 15; // Generations without beating its best before a species gets no more offspring.
+
+#[derive(Clone)]
+pub struct Clade { // What NEAT calls a species, named otherwise so it is not mixed up with map::Species.
+    pub id: usize,
+    pub representative: Net, // A member of the last generation, newcomers are compared to it.
+    pub members: usize,
+    pub best_fitness: f32, // Ever.
+    pub mean_fitness: f32, // Last generation.
+    pub stagnant_for: usize,
+    pub offspring: usize, // How many of the next generation it gets to parent.
+}
+
+#[derive(Resource, Clone)]
+pub struct Speciation {
+    pub clades: Vec<Clade>,
+    pub threshold: f64,
+    pub next_id: usize,
+}
+
+impl Default for Speciation {
+    fn default() -> Self {
+        Self{clades: Vec::new(), threshold: STARTING_THRESHOLD, next_id: 0}
+    }
+}
+
+impl Speciation {
+    pub fn plan_offspring( // Fitness sharing: a species gets offspring in proportion to its average score, not its total, so a big one cannot take over.
+        &mut self,
+        clades: &[Option<usize>], // The species of every Psychic.
+        fitnesses: &[f32],
+        scores: &[f32], // What selection goes by, the fitnesses blended with novelty if any.
+    ) -> Vec<Vec<usize>> { // Who may parent each child, in the same order as the Psychics.
+        let population = clades.len();
+        let mut candidates: Vec<Vec<usize>> = Vec::with_capacity(self.clades.len());
+        let mut shares = Vec::with_capacity(self.clades.len());
+        let best_overall = fitnesses.iter().copied().fold(f32::MIN, f32::max);
+        for clade in self.clades.iter_mut(){
+            let members: Vec<usize> = (0..population).filter(|i| clades[*i] == Some(clade.id)).collect();
+            let best = members.iter().map(|i| fitnesses[*i]).fold(f32::MIN, f32::max);
+            clade.mean_fitness = members.iter().map(|i| fitnesses[*i]).sum::<f32>() / members.len().max(1) as f32;
+            if best > clade.best_fitness {
+                clade.best_fitness = best;
+                clade.stagnant_for = 0;
+            } else {
+                clade.stagnant_for += 1;
+            }
+            let culled = clade.stagnant_for >= STAGNATION_LIMIT && best < best_overall; // The species holding the best one is always spared.
+            shares.push(if culled || members.is_empty() { 0. } else { members.iter().map(|i| scores[*i]).sum::<f32>() / members.len() as f32 });
+            candidates.push(members);
+        }
+        let total: f32 = shares.iter().sum();
+        for (clade, share) in self.clades.iter_mut().zip(shares.iter()){
+            clade.offspring = match *share > 0. {
+                true => ((population as f32 * share / total).floor() as usize).max(1), // Protected: even a weak species gets one.
+                false => 0,
+            };
+        }
+        let Some(best_clade) = (0..shares.len()).max_by(|a, b| shares[*a].total_cmp(&shares[*b])) else {
+            return vec![(0..population).collect(); population]; // Nobody was assigned a species.
+        };
+        while self.clades.iter().map(|c| c.offspring).sum::<usize>() > population {
+            let Some(biggest) = self.clades.iter_mut().filter(|c| c.offspring > 1).max_by_key(|c| c.offspring) else { break };
+            biggest.offspring -= 1;
+        }
+        while self.clades.iter().map(|c| c.offspring).sum::<usize>() < population {
+            self.clades[best_clade].offspring += 1;
+        }
+        let mut plan = Vec::with_capacity(population);
+        for (clade, members) in self.clades.iter().zip(candidates){
+            for _ in 0..clade.offspring {
+                plan.push(members.clone());
+            }
+        }
+        plan.truncate(population); // Only if there were more species than Psychics, each one protected.
+        for culled in self.clades.iter().filter(|c| c.offspring == 0){
+            info!("Species #{} went {} generations without improving, it leaves no offspring.", culled.id, culled.stagnant_for);
+        }
+        self.clades.retain(|c| c.offspring > 0);
+        plan
+    }
+    pub fn summary(&self) -> String {
+        self.clades.iter()
+            .map(|c| format!("#{}: {} members, best {:.0}, mean {:.0}, stale {}, {} offspring", c.id, c.members, c.best_fitness, c.mean_fitness, c.stagnant_for, c.offspring))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+pub fn speciate( // Runs once a generation is fully simulated. Sorts every brain into the first species close enough, or a new one.
+    config: Res<SimulationSettings>,
+    settings: Res<EvolutionSettings>,
+    mut speciation: ResMut<Speciation>,
+    mut psychics: Query<&mut Soul>,
+){
+    if config.current_turn < config.max_turn_number {
+        return;
+    }
+    if settings.target_species == 0 {
+        if !speciation.clades.is_empty() { // Just switched off.
+            *speciation = Speciation::default();
+            psychics.iter_mut().for_each(|mut soul| soul.clade = None);
+        }
+        return;
+    }
+    let threshold = speciation.threshold;
+    let mut representatives: Vec<Option<Net>> = vec![None; speciation.clades.len()];
+    for clade in speciation.clades.iter_mut(){
+        clade.members = 0;
+    }
+    for mut soul in psychics.iter_mut(){
+        let id = match speciation.clades.iter().position(|c| c.representative.distance(&soul.nn) < threshold) {
+            Some(index) => {
+                speciation.clades[index].members += 1;
+                representatives[index].get_or_insert_with(|| soul.nn.clone());
+                speciation.clades[index].id
+            }
+            None => {
+                let id = speciation.next_id;
+                speciation.next_id += 1;
+                speciation.clades.push(Clade{id, representative: soul.nn.clone(), members: 1, best_fitness: f32::MIN, mean_fitness: 0., stagnant_for: 0, offspring: 0});
+                representatives.push(None); // It already is its own representative.
+                id
+            }
+        };
+        soul.clade = Some(id);
+    }
+    for (clade, representative) in speciation.clades.iter_mut().zip(representatives){
+        if let Some(representative) = representative {
+            clade.representative = representative;
+        }
+    }
+    speciation.clades.retain(|c| c.members > 0);
+    let count = speciation.clades.len();
+    speciation.threshold = match count.cmp(&settings.target_species) {
+        std::cmp::Ordering::Less => (threshold - THRESHOLD_STEP).max(THRESHOLD_STEP),
+        std::cmp::Ordering::Greater => threshold + THRESHOLD_STEP,
+        std::cmp::Ordering::Equal => threshold,
+    };
+}
+
+#[derive(Component)]
+pub struct CladeLabel;
+
+fn draw_clade_label(
+    mut commands: Commands,
+){
+    commands.spawn((TextBundle::from_section(
+        "",
+        TextStyle {
+            font_size: 14.,
+            color: Color::WHITE,
+            ..default()
+        },
+    ).with_style(Style {
+        position_type: PositionType::Absolute,
+        bottom: Val::Px(14.),
+        right: Val::Px(4.),
+        ..default()
+    }), CladeLabel));
+}
+
+fn update_clade_label(
+    speciation: Option<Res<Speciation>>,
+    mut label: Query<&mut Text, With<CladeLabel>>,
+){
+    let Some(speciation) = speciation else { return };
+    if !speciation.is_changed() {
+        return;
+    }
+    for mut text in label.iter_mut(){
+        text.sections[0].value = match speciation.clades.is_empty() {
+            true => String::new(),
+            false => format!("Species (threshold {:.2})\n{}", speciation.threshold, speciation.summary()),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clade(id: usize, best_fitness: f32, stagnant_for: usize) -> Clade {
+        Clade{id, representative: Net::new(vec![2, 2], &mut rand::thread_rng()), members: 0, best_fitness, mean_fitness: 0., stagnant_for, offspring: 0}
+    }
+
+    #[test]
+    fn every_child_gets_parents_from_one_species() {
+        let mut speciation = Speciation{clades: vec![clade(0, 0., 0), clade(1, 0., 0)], ..default()};
+        let clades = [Some(0), Some(0), Some(1), Some(1), Some(1), Some(0)];
+        let fitnesses = [1., 2., 3., 4., 5., 6.];
+        let plan = speciation.plan_offspring(&clades, &fitnesses, &fitnesses);
+        assert_eq!(plan.len(), clades.len());
+        assert_eq!(speciation.clades.iter().map(|c| c.offspring).sum::<usize>(), clades.len());
+        for candidates in plan.iter(){
+            assert!(candidates == &vec![0, 1, 5] || candidates == &vec![2, 3, 4]);
+        }
+    }
+
+    #[test]
+    fn shares_go_by_average_not_total() {
+        let mut speciation = Speciation{clades: vec![clade(0, 0., 0), clade(1, 0., 0)], ..default()};
+        let clades = [Some(0), Some(0), Some(0), Some(0), Some(0), Some(0), Some(1), Some(1)];
+        let fitnesses = [1., 1., 1., 1., 1., 1., 3., 3.]; // The big species has more in total, the small one a better average.
+        speciation.plan_offspring(&clades, &fitnesses, &fitnesses);
+        assert!(speciation.clades[1].offspring > speciation.clades[0].offspring);
+    }
+
+    #[test]
+    fn stagnant_species_die_out_unless_they_hold_the_best() {
+        let mut speciation = Speciation{clades: vec![clade(0, 10., STAGNATION_LIMIT), clade(1, 10., STAGNATION_LIMIT)], ..default()};
+        let clades = [Some(0), Some(0), Some(1), Some(1)];
+        let fitnesses = [1., 2., 5., 3.]; // Neither beats its best, only the second holds the best of this generation.
+        let plan = speciation.plan_offspring(&clades, &fitnesses, &fitnesses);
+        assert_eq!(speciation.clades.iter().map(|c| c.id).collect::<Vec<_>>(), vec![1]);
+        assert!(plan.iter().all(|candidates| candidates == &vec![2, 3]));
+    }
+
+    #[test]
+    fn anyone_may_parent_without_species() {
+        let mut speciation = Speciation::default();
+        let plan = speciation.plan_offspring(&[None, None, None], &[1., 2., 3.], &[1., 2., 3.]);
+        assert_eq!(plan, vec![vec![0, 1, 2]; 3]);
+    }
+}
@@ -1,31 +1,85 @@
-use std::time::Duration;
+use std::{collections::HashMap, path::PathBuf, time::Duration};
 use bevy::prelude::*;
 use bevy_tweening::{Animator, EaseFunction, lens::TransformPositionLens, Tween};
 
-use crate::{psychics::{FinishedTrace, Trace}, map::Species};
+use crate::{axiom::Axiom, psychics::{FinishedTrace, Soul, Trace, TheatreBundle}, map::{Map, Species}, config::Config, replay::{Replay, list_replays}, inspector::Inspected, compare::Compared, SpriteSheetHandle};
 
 pub struct TheatrePlugin;
 
 impl Plugin for TheatrePlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(TheatreSettings{time_between_turns: Timer::new(Duration::from_millis(200), TimerMode::Repeating), current_turn: 0, max_turn_number: 100});
-        app.add_systems(Update, time_passes);
-        app.add_systems(Update, ship_gen_to_theatre);
+        app.insert_resource(TheatreSettings{
+            time_between_turns: Timer::new(Duration::from_millis(SPEED_PRESETS[DEFAULT_SPEED]), TimerMode::Repeating),
+            current_turn: 0,
+            requested_turn: Some(0),
+            max_turn_number: 100,
+            speed: DEFAULT_SPEED,
+            paused: false,
+            looping: false,
+            highlight_top: 1,
+        });
+        app.add_systems(Update, (playback_controls, time_passes).chain());
+        app.add_systems(Update, tint_actors);
+        let config = app.world.get_resource::<Config>().cloned().unwrap_or_default();
+        if config.replay.is_none() { // Nothing is training when watching a replay.
+            app.add_systems(Update, ship_gen_to_theatre);
+        }
+        if let Some(path) = config.replay {
+            let directory = path.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+            let mut files = list_replays(&directory);
+            let current = match files.iter().position(|f| f == &path) {
+                Some(i) => i,
+                None => {
+                    files = vec![path];
+                    0
+                }
+            };
+            app.insert_resource(ReplayViewer{files, current, requested: Some(current)});
+            app.add_systems(Update, (browse_replays, open_replay).chain());
+        }
     }
 }
 
+#[derive(Resource)]
+pub struct ReplayViewer { // Only exists when watching saved replays.
+    pub files: Vec<PathBuf>,
+    pub current: usize,
+    pub requested: Option<usize>,
+}
+
 pub const TILE_SIZE: f32 = 16.;
+pub const SPEED_PRESETS: [u64; 5] = [800, 400, 200, 100, 50]; // Milliseconds between turns.
+pub const DEFAULT_SPEED: usize = 2;
 
 #[derive(Resource)]
 pub struct TheatreSettings {
     pub time_between_turns: Timer,
-    pub current_turn: usize,
+    pub current_turn: usize, // The turn currently on screen.
+    pub requested_turn: Option<usize>, // The turn to show next, if something asked to jump there.
     pub max_turn_number: usize,
+    pub speed: usize, // Index in SPEED_PRESETS.
+    pub paused: bool,
+    pub looping: bool,
+    pub highlight_top: usize, // How many of the fittest Psychics stand out, 0 for none.
+}
+
+impl TheatreSettings {
+    pub fn set_speed(&mut self, speed: usize) {
+        self.speed = speed.min(SPEED_PRESETS.len() - 1);
+        self.time_between_turns.set_duration(Duration::from_millis(SPEED_PRESETS[self.speed]));
+    }
 }
 
-fn ship_gen_to_theatre(
-    ship: Query<&Trace>,
-    mut theatre: Query<&mut FinishedTrace>,
+const HIGHLIGHT_INSPECTED: Color = Color::rgb(1., 0.9, 0.2);
+const HIGHLIGHT_BEST: Color = Color::rgb(0.3, 1., 1.);
+const HIGHLIGHT_COMPARED: Color = Color::rgb(1., 0.5, 1.);
+const HIGHLIGHT_PRESETS: [usize; 4] = [0, 1, 3, 10];
+
+fn ship_gen_to_theatre( // Every theatre creature knows which simulated creature it displays, so nothing gets mixed up.
+    mut commands: Commands,
+    ship: Query<(Entity, &Trace, Option<&Soul>)>,
+    mut theatre: Query<(Entity, &mut FinishedTrace), Without<Compared>>,
+    tex_handle: Res<SpriteSheetHandle>,
     keys: Res<Input<KeyCode>>,
     //psy_sets: Res<PsychicSettings>,
     mut config: ResMut<TheatreSettings>,
@@ -33,58 +87,241 @@ fn ship_gen_to_theatre(
     if !keys.just_pressed(KeyCode::Space) {
         return;
     }
-    let mut all = Vec::new();
-    for tracer in ship.iter(){
-        all.push((&tracer.shipped_positions, &tracer.shipped_identity));
+    let mut shipped: Vec<FinishedTrace> = ship.iter()
+        .filter(|(_, tracer, _)| !tracer.shipped_positions.is_empty()) // Benched, or it just joined.
+        .map(|(entity, tracer, soul)| FinishedTrace{
+            positions: tracer.shipped_positions.clone(),
+            identity: tracer.shipped_identity.clone(),
+            source: Some(entity),
+            fitness: tracer.shipped_fitness,
+            rank: None,
+            objectives: tracer.shipped_objectives.clone(),
+            front: tracer.shipped_front,
+            actions: tracer.shipped_actions.clone(),
+            records: tracer.shipped_records.clone(),
+            brain: tracer.shipped_brain.clone(),
+            action_choices: soul.map(|s| s.action_choices.clone()).unwrap_or_default(),
+        })
+        .collect();
+    let ranks = rank_by_fitness(&shipped.iter().map(|t| (t.is_psychic(), t.fitness)).collect::<Vec<_>>());
+    for (trace, rank) in shipped.iter_mut().zip(ranks){
+        trace.rank = rank;
+    }
+    if let Some(turns) = shipped.iter().map(|t| t.positions.len()).max() { // Generations can change length while training.
+        config.max_turn_number = turns;
+    }
+    let mut by_source: HashMap<Entity, FinishedTrace> = shipped.into_iter().filter_map(|t| Some((t.source?, t))).collect();
+    for (actor, mut displayed) in theatre.iter_mut(){
+        let Some(source) = displayed.source else { continue }; // Shown from a replay or a champion's trial, not ours to replace.
+        match by_source.remove(&source) {
+            Some(trace) => *displayed = trace,
+            None => commands.entity(actor).despawn(), // Its creature sat this generation out.
+        }
+    }
+    for trace in by_source.into_values(){ // Creatures which had nobody displaying them yet.
+        let (x, y) = trace.positions[0];
+        let species = trace.identity.first().copied().unwrap_or(Species::Wall);
+        commands.spawn(TheatreBundle::new(&tex_handle).with_sprite(get_texture_id(species)).with_position(x, y).with_species(species).with_trace(trace));
+    }
+    config.requested_turn = Some(0);
+}
+
+pub fn rank_by_fitness( // Takes (is it a Psychic, fitness) pairs, and gives back where each Psychic stands. 0 is the best.
+    traces: &[(bool, f32)]
+) -> Vec<Option<usize>> {
+    let mut order: Vec<usize> = (0..traces.len()).filter(|i| traces[*i].0).collect();
+    order.sort_by(|a, b| traces[*b].1.total_cmp(&traces[*a].1));
+    let mut ranks = vec![None; traces.len()];
+    for (rank, i) in order.into_iter().enumerate(){
+        ranks[i] = Some(rank);
+    }
+    ranks
+}
+
+fn tint_actors( // The inspected creature in yellow, the fittest ones in cyan. Overlaid comparisons in faded pink.
+    config: Res<TheatreSettings>,
+    inspected: Res<Inspected>,
+    mut actors: Query<(Entity, &FinishedTrace, &mut TextureAtlasSprite, Option<&Compared>)>,
+){
+    for (entity, trace, mut sprite, compared) in actors.iter_mut(){
+        let faded = compared.is_some_and(|c| c.faded);
+        let color = if inspected.actor == Some(entity) {
+            HIGHLIGHT_INSPECTED
+        } else if trace.rank.is_some_and(|r| r < config.highlight_top) {
+            HIGHLIGHT_BEST
+        } else if faded {
+            HIGHLIGHT_COMPARED
+        } else {
+            Color::WHITE
+        };
+        let color = if faded { color.with_a(0.6) } else { color };
+        if sprite.color != color {
+            sprite.color = color;
+        }
+    }
+}
+
+fn browse_replays( // [ and ] to hop between the replays saved in the same folder.
+    keys: Res<Input<KeyCode>>,
+    mut viewer: ResMut<ReplayViewer>,
+){
+    if keys.just_pressed(KeyCode::BracketLeft) && viewer.current > 0 {
+        viewer.requested = Some(viewer.current - 1);
+    }
+    if keys.just_pressed(KeyCode::BracketRight) && viewer.current + 1 < viewer.files.len() {
+        viewer.requested = Some(viewer.current + 1);
+    }
+}
+
+fn open_replay(
+    mut commands: Commands,
+    mut viewer: ResMut<ReplayViewer>,
+    actors: Query<Entity, (With<FinishedTrace>, Without<Compared>)>,
+    tex_handle: Res<SpriteSheetHandle>,
+    mut map: ResMut<Map>,
+    mut config: ResMut<TheatreSettings>,
+){
+    let Some(index) = viewer.requested.take() else { return };
+    let path = viewer.files[index].clone();
+    let replay = match Replay::load(&path) {
+        Ok(replay) => replay,
+        Err(e) => {
+            warn!("Could not open the replay at {}: {e}", path.display());
+            return;
+        }
+    };
+    for actor in actors.iter(){
+        commands.entity(actor).despawn();
+    }
+    (map.width, map.height) = (replay.width, replay.height); // So the arena background fits.
+    (map.shipped_width, map.shipped_height) = (replay.width, replay.height); // And the overlays.
+    for actor in replay_actors(&replay, &tex_handle){
+        commands.spawn(actor);
+    }
+    info!("Watching generation {} ({}).", replay.generation, path.display());
+    viewer.current = index;
+    config.requested_turn = Some(0);
+    config.max_turn_number = replay.turn_count();
+}
+
+pub fn replay_actors( // Theatre creatures for everything in a saved replay, ranked by fitness.
+    replay: &Replay,
+    tex_handle: &SpriteSheetHandle,
+) -> Vec<TheatreBundle> {
+    let ranks = rank_by_fitness(&replay.entities.iter().map(|e| (e.identity.first().is_some_and(|s| s.is_psychic()), e.fitness)).collect::<Vec<_>>());
+    let mut actors = Vec::new();
+    for (entity, rank) in replay.entities.iter().zip(ranks){
+        let Some(&(x, y)) = entity.positions.first() else { continue };
+        let species = entity.identity.first().copied().unwrap_or(Species::Wall);
+        actors.push(TheatreBundle::new(tex_handle)
+            .with_sprite(get_texture_id(species))
+            .with_position(x, y)
+            .with_species(species)
+            .with_trace(FinishedTrace{
+                positions: entity.positions.clone(),
+                identity: entity.identity.clone(),
+                fitness: entity.fitness,
+                rank,
+                actions: entity.actions.clone(),
+                records: entity.records.clone(),
+                action_choices: entity.action_choices.clone(),
+                ..default()
+            }));
+    }
+    actors
+}
+
+fn playback_controls( // K pauses, arrows step and change speed, Home restarts, R toggles looping, B highlights more or fewer of the best.
+    keys: Res<Input<KeyCode>>,
+    mut config: ResMut<TheatreSettings>,
+){
+    if keys.just_pressed(KeyCode::K) {
+        config.paused = !config.paused;
+    }
+    if keys.just_pressed(KeyCode::R) {
+        config.looping = !config.looping;
+    }
+    if keys.just_pressed(KeyCode::B) {
+        let next = HIGHLIGHT_PRESETS.iter().position(|h| *h == config.highlight_top).map_or(0, |i| (i + 1) % HIGHLIGHT_PRESETS.len());
+        config.highlight_top = HIGHLIGHT_PRESETS[next];
+    }
+    if keys.just_pressed(KeyCode::Home) {
+        config.requested_turn = Some(0);
+    }
+    if keys.just_pressed(KeyCode::Right) {
+        config.paused = true;
+        config.requested_turn = Some(config.current_turn + 1);
+    }
+    if keys.just_pressed(KeyCode::Left) {
+        config.paused = true;
+        config.requested_turn = Some(config.current_turn.saturating_sub(1));
+    }
+    let mut speed = config.speed;
+    if keys.just_pressed(KeyCode::Up) {
+        speed = (speed + 1).min(SPEED_PRESETS.len() - 1);
     }
-    let mut index = 0;
-    for mut displayed in theatre.iter_mut(){ // Ferris, forgive me for what just unfolded here - 13th of November, 2023
-        displayed.positions = Vec::new();
-        displayed.identity = Vec::new();
-        index += 1;
-        if index == all.len() {break;}
-        let result = (all[index].0.clone(), all[index].1.clone());
-        (displayed.positions, displayed.identity) = result;
+    if keys.just_pressed(KeyCode::Down) {
+        speed = speed.saturating_sub(1);
+    }
+    if speed != config.speed {
+        config.set_speed(speed);
     }
-    config.current_turn = 0;
 }
 
 fn time_passes(
     time: Res<Time>,
     mut config: ResMut<TheatreSettings>,
-    mut theatre_actors: Query<(&Transform, &mut Animator<Transform>, &FinishedTrace, &mut TextureAtlasSprite)>,
+    mut theatre_actors: Query<(Entity, &Transform, &mut Animator<Transform>, &FinishedTrace, &mut TextureAtlasSprite)>,
+    compared: Query<&Compared>,
 ){
     config.time_between_turns.tick(time.delta());
-    if config.time_between_turns.finished() {
-        for (transform, mut anim, trace, mut sprite) in theatre_actors.iter_mut(){
-            if trace.positions.len() <= config.current_turn || config.current_turn >= config.max_turn_number{
-                continue;
-            }
-            let anim_time = if config.current_turn == 0 { 500 } else { 199 };
-            let (x, y) = (trace.positions[config.current_turn].0, trace.positions[config.current_turn].1);
-            let start = transform.translation;
-            let tween = Tween::new( // Cool rotation if the creature doesn't move or casts an Axiom?
-                EaseFunction::QuadraticInOut,
-                Duration::from_millis(anim_time),
-                TransformPositionLens {
-                    start,
-                    end: Vec3::new(TILE_SIZE * x as f32, TILE_SIZE * y as f32, 0.),
-                },
-            );
-            anim.set_tweenable(tween);
-            let new_sprite = trace.identity[config.current_turn];
-            let sprite_id = get_texture_id(new_sprite);
-            //if sprite_id == 5 {dbg!(&trace.identity);}
-            if sprite.index != sprite_id{
-                sprite.index = sprite_id;
-            }
-
+    if config.time_between_turns.just_finished() && !config.paused && config.requested_turn.is_none() {
+        if config.current_turn + 1 < config.max_turn_number {
+            config.requested_turn = Some(config.current_turn + 1);
+        }
+        else if config.looping {
+            config.requested_turn = Some(0);
+        }
+    }
+    let Some(turn) = config.requested_turn.take() else { return };
+    let turn = turn.min(config.max_turn_number.saturating_sub(1));
+    let stepped = turn == config.current_turn + 1;
+    let anim_time = match turn {
+        0 => Duration::from_millis(500),
+        _ if stepped => config.time_between_turns.duration().saturating_sub(Duration::from_millis(1)),
+        _ => Duration::from_millis(1), // Scrubbing or stepping back, there is nothing to slide.
+    };
+    for (actor, transform, mut anim, trace, mut sprite) in theatre_actors.iter_mut(){
+        if trace.positions.len() <= turn{
+            continue;
+        }
+        let (x, y) = (trace.positions[turn].0, trace.positions[turn].1);
+        let end = Vec3::new(TILE_SIZE * x as f32, TILE_SIZE * y as f32, 0.) + compared.get(actor).map_or(Vec3::ZERO, |c| c.offset);
+        let mut start = transform.translation;
+        let teleported = matches!(trace.actions.get(turn.wrapping_sub(1)), Some(Axiom::Teleport { .. }));
+        if turn != 0 && (!stepped || teleported || start.distance(end) > TILE_SIZE * 1.5) {
+            start = end; // Jumped in time or space, or wrapped around a toroidal arena. Sliding across the whole screen would look silly.
+        }
+        let tween = Tween::new( // Cool rotation if the creature doesn't move or casts an Axiom?
+            EaseFunction::QuadraticInOut,
+            anim_time,
+            TransformPositionLens {
+                start,
+                end,
+            },
+        );
+        anim.set_tweenable(tween);
+        let Some(&new_sprite) = trace.identity.get(turn) else { continue };
+        let sprite_id = get_texture_id(new_sprite);
+        //if sprite_id == 5 {dbg!(&trace.identity);}
+        if sprite.index != sprite_id{
+            sprite.index = sprite_id;
         }
-        config.current_turn += 1;
     }
+    config.current_turn = turn;
 }
 
-fn get_texture_id(
+pub fn get_texture_id(
     species: Species
 )-> usize{
     match species{
@@ -93,5 +330,11 @@ fn get_texture_id(
         Species::Psychic => 0,
         Species::Nothing => 2,
         Species::TermiPainted => 55,
+        Species::Hunter => 10,
+        Species::Caught => 18,
+        Species::Red => 21,
+        Species::Blue => 11,
+        Species::RedPainted => 31,
+        Species::BluePainted => 30,
     }
 }
\ No newline at end of file
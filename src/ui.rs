@@ -1,46 +1,103 @@
-use bevy::prelude::*;
+use bevy::{input::mouse::{MouseScrollUnit, MouseWheel}, prelude::*, window::PrimaryWindow};
 
-use crate::simulation::{PLAY_AREA_HEIGHT, PLAY_AREA_WIDTH};
+use crate::{compare::{Compared, Comparison}, map::Map, psychics::FinishedTrace, theatre::{TILE_SIZE, TheatreSettings}};
 
 pub struct UIPlugin;
 
 impl Plugin for UIPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(PreStartup, draw_black_square);
-        app.add_systems(Update, character_movement);
-        app.add_systems(Update, zoom_2d);
+        app.add_systems(Update, resize_black_square);
+        app.insert_resource(CameraFollow{enabled: false});
+        app.insert_resource(CameraDrag{pressed_at: None, last_cursor: Vec2::ZERO, dragged: false});
+        app.insert_resource(CameraFit{requested: true}); // Frame the arena on startup.
+        app.add_systems(Update, (keep_framing, character_movement, drag_camera, scroll_zoom, zoom_2d, fit_arena, follow_best).chain());
+        app.add_systems(Startup, draw_timeline);
+        app.add_systems(Update, (scrub_timeline, update_timeline).chain());
     }
 }
 
+#[derive(Resource)]
+pub struct CameraFollow { // F toggles it. The camera then keeps the fittest Psychic in the middle of the screen.
+    pub enabled: bool,
+}
+
+#[derive(Resource)]
+pub struct CameraDrag { // Left or middle mouse button held on the arena pans the camera.
+    pub pressed_at: Option<Vec2>, // Where the cursor was when the button went down.
+    last_cursor: Vec2,
+    pub dragged: bool, // Moved far enough to be a drag and not a click.
+}
+
+#[derive(Resource)]
+pub struct CameraFit { // Z asks for the whole arena to be framed.
+    pub requested: bool,
+}
+
+const DRAG_THRESHOLD: f32 = 4.; // In pixels.
+const MIN_ZOOM: f32 = 0.25;
+const MAX_ZOOM: f32 = 5.;
+
+#[derive(Component)]
+pub struct Timeline;
+
+#[derive(Component)]
+pub struct TimelineFill;
+
+#[derive(Component)]
+pub struct PlaybackLabel;
+
+#[derive(Component)]
+pub struct ArenaBackground{
+    border: f32, // Extra size on top of the play area, the white frame is one tile wider.
+}
+
 fn draw_black_square(
     mut commands: Commands
 
 ){
-    commands.spawn(SpriteBundle {
+    commands.spawn((SpriteBundle {
         sprite: Sprite {
             color: Color::rgb(1., 1., 1.),
-            custom_size: Some(Vec2::new(PLAY_AREA_WIDTH as f32 * 16. + 16., PLAY_AREA_HEIGHT as f32 * 16. + 16.)),
             ..default()
         },
-        transform: Transform::from_translation(Vec3::new(PLAY_AREA_WIDTH as f32 / 2. * 16., PLAY_AREA_HEIGHT as f32 / 2. * 16., 0.)),
         ..default()
-    });
-    commands.spawn(SpriteBundle {
+    }, ArenaBackground{border: TILE_SIZE}));
+    commands.spawn((SpriteBundle {
         sprite: Sprite {
             color: Color::rgb(0., 0., 0.),
-            custom_size: Some(Vec2::new(PLAY_AREA_WIDTH as f32 * 16., PLAY_AREA_HEIGHT as f32 * 16.)),
             ..default()
         },
-        transform: Transform::from_translation(Vec3::new((PLAY_AREA_WIDTH) as f32 / 2. * 16., (PLAY_AREA_HEIGHT) as f32 / 2. * 16., 0.)),
         ..default()
-    });
+    }, ArenaBackground{border: 0.}));
+}
+
+fn resize_black_square( // Follows the map dimensions, so that the arena can grow or shrink between generations.
+    map: Res<Map>,
+    mut background: Query<(&mut Sprite, &mut Transform, &ArenaBackground)>,
+){
+    let (width, height) = (map.width as f32 * TILE_SIZE, map.height as f32 * TILE_SIZE);
+    for (mut sprite, mut transform, frame) in background.iter_mut(){
+        let size = Vec2::new(width + frame.border, height + frame.border);
+        if sprite.custom_size != Some(size){
+            sprite.custom_size = Some(size);
+            transform.translation = Vec3::new(width / 2., height / 2., 0.);
+        }
+    }
 }
 
 fn character_movement(
     mut characters: Query<(&mut Transform, &Camera2d)>,
     input: Res<Input<KeyCode>>,
     time: Res<Time>,
+    mut follow: ResMut<CameraFollow>,
 ) {
+    if input.just_pressed(KeyCode::F) {
+        follow.enabled = !follow.enabled;
+    }
+    if input.any_pressed([KeyCode::W, KeyCode::A, KeyCode::S, KeyCode::D]) {
+        follow.enabled = false; // Taking the wheel back.
+    }
     for (mut transform, _) in &mut characters {
         if input.pressed(KeyCode::W) {
             transform.translation.y += 1000.0 * time.delta_seconds();
@@ -57,6 +114,133 @@ fn character_movement(
     }
 }
 
+fn drag_camera(
+    mouse: Res<Input<MouseButton>>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    ui: Query<&Interaction>, // The timeline, the settings panel and their buttons.
+    mut drag: ResMut<CameraDrag>,
+    mut camera: Query<(&mut Transform, &OrthographicProjection), With<Camera2d>>,
+    mut follow: ResMut<CameraFollow>,
+){
+    let buttons = [MouseButton::Left, MouseButton::Middle];
+    if !mouse.any_pressed(buttons) {
+        if drag.pressed_at.is_some() {
+            drag.pressed_at = None; // Keeps "dragged" around, so the click selection knows to ignore this release.
+        }
+        return;
+    }
+    let Some(cursor) = window.get_single().ok().and_then(|w| w.cursor_position()) else { return };
+    if mouse.any_just_pressed(buttons) {
+        if ui.iter().any(|i| *i != Interaction::None) {
+            return; // Busy with the interface, not panning.
+        }
+        drag.pressed_at = Some(cursor);
+        drag.last_cursor = cursor;
+        drag.dragged = false;
+    }
+    let Some(start) = drag.pressed_at else { return };
+    if !drag.dragged && cursor.distance(start) > DRAG_THRESHOLD {
+        drag.dragged = true;
+    }
+    if drag.dragged && cursor != drag.last_cursor {
+        let Ok((mut transform, projection)) = camera.get_single_mut() else { return };
+        let delta = (cursor - drag.last_cursor) * projection.scale;
+        transform.translation.x -= delta.x;
+        transform.translation.y += delta.y; // The cursor counts downwards, the world upwards.
+        follow.enabled = false;
+        drag.last_cursor = cursor;
+    }
+}
+
+fn scroll_zoom( // Zooms around the cursor, so whatever is under it stays there.
+    mut wheel: EventReader<MouseWheel>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    mut camera: Query<(&mut Transform, &mut OrthographicProjection), With<Camera2d>>,
+){
+    let scroll: f32 = wheel.read().map(|e| match e.unit {
+        MouseScrollUnit::Line => e.y,
+        MouseScrollUnit::Pixel => e.y / 40.,
+    }).sum();
+    if scroll == 0. {
+        return;
+    }
+    let Ok(window) = window.get_single() else { return };
+    let Ok((mut transform, mut projection)) = camera.get_single_mut() else { return };
+    let old_scale = projection.scale;
+    projection.scale = (old_scale * 0.9_f32.powf(scroll)).clamp(MIN_ZOOM, MAX_ZOOM);
+    let Some(cursor) = window.cursor_position() else { return };
+    let from_centre = (cursor - Vec2::new(window.width(), window.height()) / 2.) * Vec2::new(1., -1.);
+    let point = transform.translation.truncate() + from_centre * old_scale;
+    let centre = point - from_centre * projection.scale;
+    transform.translation = centre.extend(transform.translation.z);
+}
+
+fn fit_arena( // Z frames the whole arena, and the compared one if it is beside it.
+    keys: Res<Input<KeyCode>>,
+    map: Res<Map>,
+    comparison: Option<Res<Comparison>>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    mut fit: ResMut<CameraFit>,
+    mut follow: ResMut<CameraFollow>,
+    mut camera: Query<(&mut Transform, &mut OrthographicProjection), With<Camera2d>>,
+){
+    if keys.just_pressed(KeyCode::Z) {
+        fit.requested = true;
+    }
+    if !fit.requested {
+        return;
+    }
+    let Ok(window) = window.get_single() else { return };
+    let Ok((mut transform, mut projection)) = camera.get_single_mut() else { return };
+    fit.requested = false;
+    follow.enabled = false;
+    let mut size = Vec2::new(map.width as f32, map.height as f32) * TILE_SIZE;
+    if let Some(comparison) = comparison {
+        size.x += comparison.extra_width(&map);
+        size.y = size.y.max(comparison.height as f32 * TILE_SIZE);
+    }
+    let margin = 2. * TILE_SIZE; // Room for the white frame.
+    let scale = ((size.x + margin) / window.width()).max((size.y + margin) / window.height());
+    projection.scale = scale.clamp(MIN_ZOOM, MAX_ZOOM);
+    transform.translation = (size / 2.).extend(transform.translation.z);
+}
+
+fn keep_framing( // Resizing the window keeps showing the same part of the world, instead of cropping or padding it.
+    window: Query<&Window, With<PrimaryWindow>>,
+    mut last_size: Local<Option<Vec2>>,
+    mut camera: Query<&mut OrthographicProjection, With<Camera2d>>,
+){
+    let Ok(window) = window.get_single() else { return };
+    let size = Vec2::new(window.width(), window.height());
+    if size.x <= 0. || size.y <= 0. {
+        return; // Minimised.
+    }
+    let Some(old) = last_size.replace(size) else { return };
+    if old == size {
+        return;
+    }
+    let Ok(mut projection) = camera.get_single_mut() else { return };
+    let ratio = (old.x / size.x).max(old.y / size.y);
+    projection.scale = (projection.scale * ratio).clamp(MIN_ZOOM, MAX_ZOOM);
+}
+
+fn follow_best(
+    follow: Res<CameraFollow>,
+    actors: Query<(&Transform, &FinishedTrace), Without<Compared>>,
+    mut camera: Query<&mut Transform, (With<Camera2d>, Without<FinishedTrace>)>,
+    time: Res<Time>,
+){
+    if !follow.enabled {
+        return;
+    }
+    let Some((target, _)) = actors.iter().find(|(_, trace)| trace.rank == Some(0)) else { return };
+    let Ok(mut transform) = camera.get_single_mut() else { return };
+    let goal = target.translation.truncate() + Vec2::splat(TILE_SIZE / 2.);
+    let smoothing = (5. * time.delta_seconds()).min(1.);
+    let position = transform.translation.truncate().lerp(goal, smoothing);
+    transform.translation = position.extend(transform.translation.z);
+}
+
 fn zoom_2d(
     mut q: Query<&mut OrthographicProjection, With<Camera2d>>,
     input: Res<Input<KeyCode>>,
@@ -72,13 +256,97 @@ fn zoom_2d(
     
         // always ensure you end up with sane values
         // (pick an upper and lower bound for your application)
-        projection.scale = projection.scale.clamp(0.5, 5.0);
+        projection.scale = projection.scale.clamp(MIN_ZOOM, MAX_ZOOM);
     }
     else if input.pressed(KeyCode::P) {
         let mut projection = q.single_mut();
 
         // example: zoom in
         projection.scale -= 0.8 * time.delta_seconds();
-        projection.scale = projection.scale.clamp(0.5, 5.0);
+        projection.scale = projection.scale.clamp(MIN_ZOOM, MAX_ZOOM);
+    }
+}
+fn draw_timeline( // A bar at the bottom of the window. Click or drag on it to jump to any turn.
+    mut commands: Commands,
+){
+    commands.spawn((ButtonBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(0.),
+            left: Val::Px(0.),
+            width: Val::Percent(100.),
+            height: Val::Px(12.),
+            ..default()
+        },
+        background_color: Color::rgb(0.2, 0.2, 0.2).into(),
+        ..default()
+    }, Timeline)).with_children(|parent| {
+        parent.spawn((NodeBundle {
+            style: Style {
+                width: Val::Percent(0.),
+                height: Val::Percent(100.),
+                ..default()
+            },
+            background_color: Color::rgb(0.9, 0.4, 0.7).into(),
+            ..default()
+        }, TimelineFill));
+    });
+    commands.spawn((TextBundle::from_section(
+        "",
+        TextStyle {
+            font_size: 16.,
+            color: Color::WHITE,
+            ..default()
+        },
+    ).with_style(Style {
+        position_type: PositionType::Absolute,
+        bottom: Val::Px(14.),
+        left: Val::Px(4.),
+        ..default()
+    }), PlaybackLabel));
+}
+
+fn scrub_timeline(
+    timeline: Query<(&Interaction, &Node, &GlobalTransform), With<Timeline>>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    mut config: ResMut<TheatreSettings>,
+){
+    let Ok((interaction, node, transform)) = timeline.get_single() else { return };
+    if *interaction != Interaction::Pressed {
+        return;
     }
-}
\ No newline at end of file
+    let Some(cursor) = window.get_single().ok().and_then(|w| w.cursor_position()) else { return };
+    let left = transform.translation().x - node.size().x / 2.;
+    let fraction = ((cursor.x - left) / node.size().x).clamp(0., 1.);
+    let turn = (fraction * config.max_turn_number.saturating_sub(1) as f32).round() as usize;
+    if turn != config.current_turn {
+        config.requested_turn = Some(turn);
+    }
+}
+
+fn update_timeline( // Only touches what changed since it was last drawn. TheatreSettings itself changes every frame, its timer ticks.
+    config: Res<TheatreSettings>,
+    mut fill: Query<&mut Style, With<TimelineFill>>,
+    mut label: Query<&mut Text, With<PlaybackLabel>>,
+){
+    let progress = (config.current_turn + 1) as f32 / config.max_turn_number.max(1) as f32;
+    let width = Val::Percent(progress.min(1.) * 100.);
+    for mut style in fill.iter_mut(){
+        if style.width != width {
+            style.width = width;
+        }
+    }
+    let value = format!(
+        "Turn {}/{} - {} ms per turn{}{}",
+        config.current_turn + 1,
+        config.max_turn_number,
+        config.time_between_turns.duration().as_millis(),
+        if config.paused { " - paused" } else { "" },
+        if config.looping { " - looping" } else { "" },
+    );
+    for mut text in label.iter_mut(){
+        if text.sections[0].value != value {
+            text.sections[0].value = value.clone();
+        }
+    }
+}